@@ -1,11 +1,12 @@
 use clap::{command, Arg, ArgAction, Command};
+use crate::flavor;
 
 pub struct Cli {
 }
 
 impl Cli {
     pub fn parse() -> Command {
-        command!()
+        let command = command!()
             .name("b2tf")
             .display_name("Back 2 The Future")
             .author("Patrik Jakobsson <patrik.r.jakobsson@gmail.com>")
@@ -35,6 +36,16 @@ impl Cli {
             .arg(Arg::new("paths")
                 .long("paths")
             )
+            .arg(Arg::new("color")
+                .help("when to use colors")
+                .long("color")
+                .value_parser(["auto", "always", "never"])
+            )
+            .arg(Arg::new("layout")
+                .help("how to show diffs")
+                .long("layout")
+                .value_parser(["unified", "side-by-side"])
+            )
             .subcommand(
                 Command::new("setup")
                     .about("create b2tf.log file with supplied options")
@@ -59,6 +70,19 @@ impl Cli {
                 Command::new("restart")
                     .about("delete your branch and restart the entire backport")
             )
+            .subcommand(
+                Command::new("goto")
+                    .about("restart the backport from the specified commit and keep all commits before it")
+                    .arg_required_else_help(true)
+                    .arg(Arg::new("commit to restart from")
+                        .long("hash")
+                    )
+                    .arg(Arg::new("keep resolutions")
+                        .help("keep the previous backports so their conflict resolutions can be reused")
+                        .long("keep")
+                        .action(ArgAction::SetTrue)
+                    )
+            )
             .subcommand(
                 Command::new("status")
                     .about("show status of backport")
@@ -74,6 +98,19 @@ impl Cli {
                         .long("skip")
                     )
             )
+            .subcommand(
+                Command::new("deviations")
+                    .about("list backports that differ from their upstream commit")
+            )
+            .subcommand(
+                Command::new("range-diff")
+                    .about("show the differences between every upstream commit and its backport")
+                    .arg(Arg::new("show all")
+                        .help("also show backports that are the same as upstream")
+                        .long("all")
+                        .action(ArgAction::SetTrue)
+                    )
+            )
             .subcommand(
                 Command::new("diffstat")
                     .about("show diff stat between your branch and <range stop>")
@@ -82,6 +119,10 @@ impl Cli {
                 Command::new("rebase")
                     .about("rebase the commit list")
             )
+            .subcommand(
+                Command::new("edit-list")
+                    .about("reorder, drop and squash commits in the log with an editor")
+            )
             .subcommand(
                 Command::new("update")
                     .about("update the commit log with hashes from git repo")
@@ -113,60 +154,81 @@ impl Cli {
                         .long("after")
                     )
             )
+            .subcommand(
+                Command::new("import")
+                    .about("apply a series from an mbox onto a scratch branch and add it to the log")
+                    .arg_required_else_help(true)
+                    .arg(Arg::new("mbox file")
+                        .required(true)
+                        .long("mbox")
+                    )
+                    .arg(Arg::new("base commit")
+                        .long("base")
+                        .help("commit to apply the series on, defaults to range-stop")
+                    )
+                    .arg(Arg::new("insert after this hash")
+                        .long("after")
+                        .help("insert the series after this hash instead of appending it")
+                    )
+            )
+            .subcommand(
+                Command::new("export")
+                    .about("export the backport as a patch series with a cover letter")
+                    .arg(Arg::new("output directory")
+                        .long("output")
+                        .help("directory to write the patches to, defaults to patches/ in the work directory")
+                    )
+                    .arg(Arg::new("mbox file")
+                        .long("mbox")
+                        .conflicts_with("output directory")
+                        .help("write the series into a single mbox file instead")
+                    )
+                    .arg(Arg::new("reference style")
+                        .long("style")
+                        .value_parser(["cherry-pick", "stable"])
+                        .help("how patches reference upstream, defaults to message-style or cherry-pick")
+                    )
+            )
+            .subcommand(
+                Command::new("validate")
+                    .about("check that the commits on the branch follow the conventions of the flavor")
+            )
+            .subcommand(
+                Command::new("apply-downstream")
+                    .about("apply the exported backport to the downstream tree of the flavor")
+            )
             .subcommand(
                 Command::new("compare")
-                    .about("compare two patches")
+                    .about("compare two patch files, commits or a..b ranges")
+                    .after_help("Exit codes: 0 identical, 2 same, 3 similar, 4 equivalent, 5 different, 1 on errors")
                     .arg_required_else_help(true)
+                    .arg(Arg::new("first")
+                        .conflicts_with("patch 1")
+                    )
+                    .arg(Arg::new("second")
+                        .conflicts_with("patch 2")
+                    )
                     .arg(Arg::new("patch 1")
-                        .required(true)
                         .long("p1")
                     )
                     .arg(Arg::new("patch 2")
-                        .required(true)
                         .long("p2")
                     )
             )
             .subcommand(
-                Command::new("suse")
-                    .about("SUSE specific subcommands")
+                Command::new("compare-series")
+                    .about("compare two series of patches from directories, mbox files or a..b ranges")
+                    .after_help("Exit codes are those of compare for the most different pair, 5 if patches were added or removed")
                     .arg_required_else_help(true)
-                    .arg(Arg::new("signature")
-                        .long("signature")
-                    )
-                    .arg(Arg::new("patch references")
-                        .long("references")
-                    )
-                    .arg(Arg::new("Path to SUSE kernel-source")
-                        .long("suse-kernel-source")
-                    )
-                    .subcommand(
-                        Command::new("export")
-                            .about("export all commits as SUSE patch files")
-                    )
-                    .subcommand(
-                        Command::new("unblacklist")
-                            .about("remove blacklists for patches we are backporting")
-                    )
-                    .subcommand(
-                        Command::new("apply")
-                            .about("apply all patches to the SUSE tree")
-                            .arg(Arg::new("range guard")
-                                .help("automatically guard failing patches up to this revision")
-                                .long("range-guard")
-                            )
-                            .arg(Arg::new("guard prefix")
-                                .help("Prefix to use when guarding patches")
-                                .long("guard-prefix")
-                            )
-                    )
-                    .subcommand(
-                        Command::new("unguard")
-                            .about("reapply all guarded patches in series.conf")
-                            .arg(Arg::new("guard prefix")
-                                .help("Unguard all patches with this prefix")
-                                .long("guard-prefix")
-                            )
+                    .arg(Arg::new("first series")
+                        .required(true)
                     )
-            )
+                    .arg(Arg::new("second series")
+                        .required(true)
+                    )
+            );
+
+        // Downstream flavors bring their own subcommands
+        flavor::register(command)
     }
 }
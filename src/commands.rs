@@ -1,16 +1,26 @@
 use std::process::Command;
 use std::error::Error;
 use std::path::Path;
+use std::path;
 use std::fs;
 use std::io::Write;
+use std::collections::HashMap;
 use clap::ArgMatches;
 use colored::Colorize;
 use crate::Options;
 use crate::Log;
 use crate::Util;
-use crate::patch::{CompareResult, Patch};
-use crate::git::{Git, GitSessionState};
-use unidiff::{PatchSet, PatchedFile, Hunk};
+use crate::patch::{CompareResult, FileOperation, Patch, PatchFile, PatchHunk};
+use crate::git::{Git, GitSession, GitSessionState};
+use crate::message;
+use crate::verify;
+use crate::hooks;
+use crate::hooks::Hook;
+use crate::expected;
+use crate::expected::Expected;
+use crate::render::Renderer;
+use crate::series;
+use crate::flavor;
 use mktemp::Temp;
 
 pub fn cmd_setup(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
@@ -52,12 +62,6 @@ pub fn cmd_setup(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
     if options.paths.is_some() {
         log.config.push_str(format!("paths: {}\n", &options.paths.unwrap()).as_str());
     }
-    if options.signature.is_some() {
-        log.config.push_str(format!("signature: {}\n", &options.signature.unwrap()).as_str());
-    }
-    if options.references.is_some() {
-        log.config.push_str(format!("references: {}\n", &options.references.unwrap()).as_str());
-    }
 
     log.save()
 }
@@ -86,9 +90,16 @@ pub fn cmd_populate(options: &Options, log: &mut Log) -> Result<(), Box<dyn Erro
         commits.push_str(hash.as_str());
     }
 
+    let previous = log.commits.clone();
     log.commits = commits;
     log.save()?;
 
+    if !hooks::run(options, log, Hook::PostPopulate, "")? {
+        log.commits = previous;
+        log.save()?;
+        return Err("Hook post-populate vetoed the action. Log restored".red().into());
+    }
+
     Ok(())
 }
 
@@ -155,53 +166,307 @@ fn get_commit_cache(options: &Options) -> Result<Vec<(String, String)>, Box<dyn
     Ok(cache)
 }
 
+// Compare two diffs. Returns the result and the parsed patches
+fn compare_diffs(src: &str, dst: &str) -> Result<(CompareResult, Patch, Patch), Box<dyn Error>> {
+    let mut patch1 = Patch::new();
+    let mut patch2 = Patch::new();
+
+    // Identical input needs no parsing and has no differences to report
+    if src == dst {
+        return Ok((CompareResult::Identical, patch1, patch2));
+    }
+
+    patch1.parse(src)?;
+    patch2.parse(dst)?;
+
+    Ok((patch1.compare(patch2.clone()), patch1, patch2))
+}
+
 pub fn compare_patches(src_path: &str, dst_path: &str) -> Result<CompareResult, Box<dyn Error>> {
     let src: String = fs::read_to_string(src_path)?;
     let dst: String = fs::read_to_string(dst_path)?;
 
-    if src == dst {
-        return Ok(CompareResult::Identical);
+    Ok(compare_diffs(&src, &dst)?.0)
+}
+
+// Returns why two patch files are not the same, one reason per file or hunk
+pub fn patch_differences(src_path: &str, dst_path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let (_, patch1, patch2) = compare_diffs(&fs::read_to_string(src_path)?, &fs::read_to_string(dst_path)?)?;
+
+    Ok(patch1.differences(&patch2))
+}
+
+// Returns the diff of a patch file, a commit-ish or an a..b range
+fn resolve_diff(options: &Options, what: &str) -> Result<String, Box<dyn Error>> {
+    let git_dir = options.git_dir.clone().unwrap();
+
+    if Path::new(what).is_file() {
+        return Ok(fs::read_to_string(what)?);
     }
 
-    let mut patch1 = Patch::new();
-    let mut patch2 = Patch::new();
+    if let Some((from, to)) = what.split_once("..") {
+        let to = if to.is_empty() { "HEAD" } else { to };
+        return Git::cmd(format!("diff --no-color --no-ext-diff --full-index {} {}", from, to), &git_dir)
+            .map_err(|_| format!("Not a patch file, commit or range: {}", what).red().into());
+    }
+
+    Git::diff_of(what, &git_dir).map_err(|_| format!("Not a patch file, commit or range: {}", what).red().into())
+}
 
-    patch1.parse(&src.to_string());
-    patch2.parse(&dst.to_string());
+// Returns the patch of a commit
+fn commit_patch(options: &Options, hash: &str) -> Result<Patch, Box<dyn Error>> {
+    let git_dir = options.git_dir.clone().unwrap();
+    let diff = Git::diff_of(hash, &git_dir)?;
 
-    Ok(patch1.compare(patch2))
+    let mut patch = Patch::new();
+    patch.parse(&diff)?;
+
+    Ok(patch)
 }
 
-fn compare_commits(options: &Options, hash1: &str, hash2: &str) -> Result<CompareResult, Box<dyn Error>> {
+// Tuples with (hash, paths) of commits
+type FileCache = Vec<(String, Vec<String>)>;
+
+// Parsed candidates of the file cache, None if the commit failed to parse
+type PatchCache = HashMap<String, Option<Patch>>;
+
+// Return the paths of all commits that can potentially be a partial backport
+fn get_file_cache(options: &Options) -> Result<FileCache, Box<dyn Error>> {
     let git_dir = options.git_dir.clone().unwrap();
+    let paths = options.paths.clone().unwrap();
+    let range_start = options.range_start.clone().unwrap();
+    let mut cache: FileCache = vec![];
 
-    let commit1 = Git::show(hash1, &git_dir.to_string())?;
-    let commit2 = Git::show(hash2, &git_dir.to_string())?;
+    let query = format!("log --date=format:%Y-%m-%d --format=%cd -n1 {range_start}").to_string();
+    let start_date = Git::cmd(query, &git_dir)?;
+    let start_date = start_date.trim();
 
-    let mut patch1 = Patch::new();
-    let mut patch2 = Patch::new();
+    let query = format!("log --no-merges --since \"$(date --date \"{start_date} - 12 months\")\" --format='commit %H' --name-only -- {paths}").to_string();
+    let stdout = Git::cmd(query, &git_dir)?;
+
+    for line in stdout.split("\n") {
+        if let Some(hash) = line.strip_prefix("commit ") {
+            cache.push((hash.to_string(), vec![]));
+        } else if let Some(entry) = cache.last_mut() {
+            if !line.is_empty() {
+                entry.1.push(line.to_string());
+            }
+        }
+    }
+
+    Ok(cache)
+}
+
+// Returns the commit touching the same files that most of the changes of hash are found in,
+// with its similarity score
+fn best_partial(options: &Options, hash: &str, file_cache: &FileCache, patch_cache: &mut PatchCache) -> Option<(String, f64)> {
+    // Without a patch of our own there is nothing to look for
+    let patch = commit_patch(options, hash).ok()?;
+    let paths: Vec<&str> = patch.files.iter().flat_map(|f| [PatchFile::path(&f.source_file), PatchFile::path(&f.target_file)]).collect();
+    let mut best: Option<(String, f64)> = None;
+
+    for (candidate, files) in file_cache {
+        if !files.iter().any(|f| paths.contains(&f.as_str())) {
+            continue;
+        }
+
+        // Every candidate is parsed once per run, one we fail to parse is just not considered
+        let other = match patch_cache.entry(candidate.clone()).or_insert_with(|| commit_patch(options, candidate).ok()) {
+            Some(other) => other,
+            None => continue,
+        };
+
+        // Nothing to match means nothing of ours is present
+        let similarity = patch.similarity(other);
+        if similarity.total == 0 {
+            continue;
+        }
+
+        let score = similarity.score();
+        if best.as_ref().is_none_or(|b| score > b.1) {
+            best = Some((candidate.clone(), score));
+        }
+    }
+
+    best
+}
 
-    patch1.parse(&commit1.body);
-    patch2.parse(&commit2.body);
+// Returns the similarity above which a commit is considered partially present
+fn partial_threshold(options: &Options) -> Option<f64> {
+    let threshold = options.partial_threshold.clone()?;
+
+    match threshold.strip_suffix("%") {
+        Some(percent) => percent.trim().parse::<f64>().ok().map(|p| p / 100.0),
+        None => threshold.parse::<f64>().ok(),
+    }
+}
+
+fn compare_commits(options: &Options, hash1: &str, hash2: &str) -> Result<CompareResult, Box<dyn Error>> {
+    let patch1 = commit_patch(options, hash1)?;
+    let patch2 = commit_patch(options, hash2)?;
 
     let res = patch1.compare(patch2);
 
     Ok(res)
 }
 
-pub fn cmd_compare(options: &Options) -> Result<(), Box<dyn Error>> {
-    let p1 = options.patch1.clone().unwrap();
-    let p1 = p1.as_str();
-    let p2 = options.patch2.clone().unwrap();
-    let p2 = p2.as_str();
+// Apply an mbox onto a scratch branch in a temporary worktree so the patches get hashes,
+// then add them to the log. The commits are annotated as submitted since they are not mainline
+pub fn cmd_import(options: &mut Options, log: &mut Log) -> Result<(), Box<dyn Error>> {
+    let git_dir = options.git_dir.clone().unwrap();
+    let mbox = match &options.mbox {
+        Some(mbox) => path::absolute(mbox)?,
+        None => return Err("No --mbox was provided".red().into()),
+    };
+    if !mbox.is_file() {
+        return Err(format!("No such mbox: {}", mbox.display()).red().into());
+    }
 
-    let ret = compare_patches(p1, p2)?;
+    if let Some(after) = &options.after {
+        if !log.get_all()?.iter().any(|c| c.0 == *after || c.1 == *after) {
+            return Err(format!("Hash {} not found in log", after).red().into());
+        }
+    }
 
-    println!("Result: {:?}", ret);
+    let base = options.base.clone().unwrap_or(options.range_stop.clone().unwrap());
+    let name = mbox.file_stem().unwrap().to_string_lossy().replace(" ", "-");
+    let branch = format!("b2tf-import/{}", name);
+
+    // The scratch branch outlives the worktree so the commits stay reachable
+    if Git::branch_exists(&branch, &git_dir)? {
+        return Err(format!("Branch {} already exists. Was {} imported before?", branch, mbox.display()).red().into());
+    }
+    let temp_dir = Temp::new_dir()?;
+    let worktree = temp_dir.to_path_buf().display().to_string();
+    Git::cmd(format!("worktree add -q -b {} {} {}", Git::quote(&branch), worktree, base), &git_dir)?;
+
+    let res = Git::cmd(format!("am -q --3way --message-id {}", Git::quote(&mbox.display().to_string())), &worktree);
+    if res.is_err() {
+        let _ = Git::cmd("am --abort".to_string(), &worktree);
+    }
+    Git::cmd(format!("worktree remove --force {}", worktree), &git_dir)?;
+
+    if res.is_err() {
+        Git::cmd(format!("branch -D {}", Git::quote(&branch)), &git_dir)?;
+        return Err(format!("Failed to apply {} on {}", mbox.display(), base).red().into());
+    }
+
+    let hashes = Git::cmd(format!("rev-list --reverse {}..{}", base, Git::quote(&branch)), &git_dir)?;
+    let hashes: Vec<&str> = hashes.split_whitespace().collect();
+    if hashes.is_empty() {
+        return Err(format!("No patches found in {}", mbox.display()).red().into());
+    }
+    println!("{} {} commits on {}", "Imported:".green(), hashes.len(), branch);
+
+    options.hash = Some(hashes.join(" "));
+    if options.after.is_some() {
+        cmd_insert(options, log)?;
+    } else {
+        cmd_append(options, log)?;
+    }
+
+    // Without a Message-Id the mbox is the only reference to the submission
+    for hash in hashes {
+        let msg_id = Git::cmd(format!("log -1 --format='%(trailers:key=Message-Id,valueonly)' {}", hash), &git_dir)?;
+        let msg_id = msg_id.trim();
+        let mbox_name = mbox.file_name().unwrap().to_string_lossy();
+        log.set_annotation(hash, "submitted", if msg_id.is_empty() { &mbox_name } else { msg_id })?;
+    }
 
     Ok(())
 }
 
+// Compare patch files, commits and ranges in any combination. The result is returned so
+// main can turn it into the exit code
+pub fn cmd_compare(options: &Options) -> Result<CompareResult, Box<dyn Error>> {
+    let (p1, p2) = match (&options.patch1, &options.patch2) {
+        (Some(p1), Some(p2)) => (p1.as_str(), p2.as_str()),
+        _ => return Err("compare needs two patch files, commits or ranges".red().into()),
+    };
+
+    let (ret, patch1, patch2) = compare_diffs(&resolve_diff(options, p1)?, &resolve_diff(options, p2)?)?;
+    let mut r = Renderer::new(options);
+
+    r.line(&format!("Result: {:?}", ret));
+
+    if ret < CompareResult::Identical {
+        for reason in patch1.differences(&patch2) {
+            r.line(&format!("  {}", reason));
+        }
+    }
+
+    for line in patch1.similarity(&patch2).summary() {
+        r.line(&line);
+    }
+
+    if ret < CompareResult::Same {
+        render_interdiff(&mut r, &patch1, &patch2, p1, p2);
+    }
+
+    r.finish()?;
+
+    Ok(ret)
+}
+
+// Compare two series of patches and report which patches were added, removed or changed.
+// The worst result is returned so main can turn it into the exit code
+pub fn cmd_compare_series(options: &Options) -> Result<CompareResult, Box<dyn Error>> {
+    let (s1, s2) = match (&options.patch1, &options.patch2) {
+        (Some(s1), Some(s2)) => (s1.as_str(), s2.as_str()),
+        _ => return Err("compare-series needs two patch directories, mbox files or ranges".red().into()),
+    };
+
+    let series1 = series::load(options, s1)?;
+    let series2 = series::load(options, s2)?;
+    let (pairs, removed, added) = series::pair(&series1, &series2);
+
+    let mut r = Renderer::new(options);
+    let mut worst = CompareResult::Identical;
+    let mut identical = 0;
+    let mut changed = 0;
+
+    for (i, j) in &pairs {
+        let (p1, p2) = (&series1[*i], &series2[*j]);
+        let (ret, patch1, patch2) = compare_diffs(&p1.text, &p2.text)?;
+
+        if ret == CompareResult::Identical {
+            identical += 1;
+            continue;
+        }
+
+        changed += 1;
+        let name = if p1.name == p2.name { p1.name.clone() } else { format!("{} -> {}", p1.name, p2.name) };
+        r.line(&format!("{} {} ({})", format!("Changed ({:?}):", ret).yellow(), p1.subject, name));
+        let reasons = patch1.differences(&patch2);
+        if reasons.is_empty() {
+            r.line("  description or headers differ");
+        }
+        for reason in reasons {
+            r.line(&format!("  {}", reason));
+        }
+
+        if ret < worst {
+            worst = ret;
+        }
+    }
+
+    for i in &removed {
+        r.line(&format!("{} {} ({})", "Removed:".red(), series1[*i].subject, series1[*i].name));
+    }
+    for j in &added {
+        r.line(&format!("{} {} ({})", "Added:".green(), series2[*j].subject, series2[*j].name));
+    }
+
+    if !removed.is_empty() || !added.is_empty() {
+        worst = CompareResult::Different;
+    }
+
+    r.line(&format!("{} identical, {} changed, {} removed, {} added", identical, changed, removed.len(), added.len()));
+    r.finish()?;
+
+    Ok(worst)
+}
+
 fn handle_empty_state(options: &Options, log: &mut Log) -> Result<bool, Box<dyn Error>> {
     let git_dir = options.git_dir.clone().unwrap();
     let session = Git::get_session(&git_dir)?;
@@ -218,6 +483,130 @@ fn handle_empty_state(options: &Options, log: &mut Log) -> Result<bool, Box<dyn
     Ok(false)
 }
 
+// Returns the Signed-off-by line of the backporter
+pub fn signoff_line(options: &Options) -> Result<String, Box<dyn Error>> {
+    let git_dir = options.git_dir.clone().unwrap();
+
+    let name = match &options.committer_name {
+        Some(name) => name.clone(),
+        None => Git::cmd("config user.name".to_string(), &git_dir)?.trim().to_string(),
+    };
+    let email = match &options.committer_email {
+        Some(email) => email.clone(),
+        None => Git::cmd("config user.email".to_string(), &git_dir)?.trim().to_string(),
+    };
+
+    Ok(format!("Signed-off-by: {} <{}>", name, email))
+}
+
+// Returns the git config and the commit arguments for committing a backport of upstream
+// with the configured signing and committer identity
+fn commit_args(options: &Options, upstream: &str) -> Result<(String, String), Box<dyn Error>> {
+    let git_dir = options.git_dir.clone().unwrap();
+    let mut config = String::new();
+    let mut args = String::new();
+
+    let mut name = options.committer_name.clone();
+    let mut email = options.committer_email.clone();
+
+    if options.preserve_committer {
+        let committer = Git::cmd(format!("log -1 --format='%cn%n%ce' {}", upstream), &git_dir)?;
+        let lines: Vec<&str> = committer.split("\n").collect();
+        name = Some(lines[0].to_string());
+        email = Some(lines[1].to_string());
+    }
+
+    if let Some(name) = name {
+        config.push_str(format!("-c user.name={} ", Git::quote(&name)).as_str());
+    }
+    if let Some(email) = email {
+        config.push_str(format!("-c user.email={} ", Git::quote(&email)).as_str());
+    }
+    if let Some(format) = &options.sign_format {
+        config.push_str(format!("-c gpg.format={} ", Git::quote(format)).as_str());
+    }
+
+    match options.sign_commits.as_deref() {
+        None | Some("no") | Some("false") => {},
+        Some("yes") | Some("true") => args.push_str("-S "),
+        Some(key) => args.push_str(format!("-S{} ", Git::quote(key)).as_str()),
+    }
+
+    Ok((config, args))
+}
+
+// Update the log with the commit just picked from upstream and rewrite its message if configured
+fn finish_pick(options: &Options, log: &mut Log, upstream: &str) -> Result<(), Box<dyn Error>> {
+    let git_dir = options.git_dir.clone().unwrap();
+    let (config, args) = commit_args(options, upstream)?;
+    let mut new_msg = None;
+    let flavor = flavor::from_options(options)?;
+
+    // Picks that needed manual resolution get the conflict note by default, even when nothing
+    // else rewrites messages. An empty conflict-note: in b2tf.log turns it off
+    let conflicted = log.get_annotation(upstream, "conflicts").is_some();
+
+    if message::enabled(options) || options.signoff || flavor.is_some() || conflicted {
+        let msg = Git::cmd("log -1 --format=%B".to_string(), &git_dir)?;
+        let mut rewritten = message::rewrite(options, upstream, &msg, conflicted);
+
+        if let Some(flavor) = &flavor {
+            rewritten = flavor.tag_message(options, log, upstream, &rewritten)?;
+        }
+
+        // The backporter signs off after everything else was added
+        if options.signoff {
+            let mut m = message::Message::parse(&rewritten);
+            let signoff = signoff_line(options)?;
+            if !m.contains(&signoff) {
+                m.add_trailer(&signoff);
+            }
+            rewritten = m.text();
+        }
+
+        if rewritten.trim() != msg.trim() {
+            new_msg = Some(rewritten);
+        }
+    }
+
+    // Amend the pick if the message, signature or committer must change
+    if new_msg.is_some() || !config.is_empty() || !args.is_empty() {
+        let temp_file = Temp::new_file()?;
+        let msg_arg = match new_msg {
+            Some(msg) => {
+                fs::write(&temp_file, msg)?;
+                format!("-F {}", temp_file.to_path_buf().display())
+            },
+            None => "--no-edit".to_string(),
+        };
+        Git::cmd(format!("{}commit --amend --no-verify {}{}", config, args, msg_arg), &git_dir)?;
+    }
+
+    let new_hash = Git::get_last_commit(&git_dir)?;
+    log.commit_update(upstream, &new_hash)?;
+
+    // Remember how much the backport deviates from upstream, a failed compare is left for later
+    match compare_commits(options, upstream, &new_hash) {
+        Ok(res) => log.set_annotation(upstream, "compare", &format!("{:?}", res))?,
+        Err(e) => println!("{} {}: {}", "Failed to compare with".yellow(), upstream, e),
+    }
+
+    // The hook sees the recorded backport, a veto only stops the run
+    hooks::check(options, log, Hook::PostApply, upstream)
+}
+
+// Remember which files needed manual resolution once the on-conflict hook accepted them
+fn record_conflicts(options: &Options, log: &mut Log, upstream: &str, session: &GitSession) -> Result<(), Box<dyn Error>> {
+    if log.get_annotation(upstream, "conflicts").is_some() {
+        return Ok(());
+    }
+
+    hooks::check(options, log, Hook::OnConflict, upstream)?;
+
+    let paths: Vec<String> = session.unmerged_paths.iter().map(|p| p.1.clone()).collect();
+    log.set_annotation(upstream, "conflicts", &paths.join(" "))
+}
+
 // Returns true if a patch was applied
 fn handle_git_state(options: &Options, log: &mut Log) -> Result<bool, Box<dyn Error>> {
     let git_dir = options.git_dir.clone().unwrap();
@@ -234,6 +623,8 @@ fn handle_git_state(options: &Options, log: &mut Log) -> Result<bool, Box<dyn Er
 
         // If we have conflicts then edit them
         if !session.unmerged_paths.is_empty() {
+            record_conflicts(options, log, next_hash, &session)?;
+
             let handled = cmd_edit(options, log)?;
 
             // After the edit is done we might have an empty commit
@@ -249,8 +640,7 @@ fn handle_git_state(options: &Options, log: &mut Log) -> Result<bool, Box<dyn Er
         // Check if all conflicts are resolved so we can update log and continue
         if session.unmerged_paths.is_empty() && !session.modified_paths.is_empty() {
             Git::cmd("cherry-pick --continue".to_string(), &git_dir)?;
-            let new_hash = Git::get_last_commit(&git_dir)?;
-            log.commit_update(next_hash, &new_hash)?;
+            finish_pick(options, log, next_hash)?;
             return Ok(true);
         }
     } else if session.state == GitSessionState::Rebase {
@@ -273,26 +663,151 @@ fn handle_git_state(options: &Options, log: &mut Log) -> Result<bool, Box<dyn Er
     Ok(false)
 }
 
+// Fold upstream commit into the backport of target. Returns false if the commit was skipped instead
+fn apply_squash(options: &Options, log: &mut Log, upstream: &str, target: &str) -> Result<bool, Box<dyn Error>> {
+    let git_dir = options.git_dir.clone().unwrap();
+    let target_backport = log.get_all()?.into_iter().find(|c| c.0 == target).map(|c| c.1).unwrap_or_default();
+    let session = Git::get_session(&git_dir)?;
+
+    // Pick the changes unless we're continuing an interrupted squash
+    if session.unmerged_paths.is_empty() && session.modified_paths.is_empty() {
+        let res = Git::cmd(format!("cherry-pick --no-commit {} > /dev/null", upstream), &git_dir);
+
+        if res.is_err() && Git::get_session(&git_dir)?.unmerged_paths.is_empty() {
+            return Err(format!("Failed to pick {}", upstream).red().into());
+        }
+    }
+
+    // If we have conflicts then edit them
+    let session = Git::get_session(&git_dir)?;
+    if !session.unmerged_paths.is_empty() {
+        record_conflicts(options, log, upstream, &session)?;
+
+        if cmd_edit(options, log)? {
+            Git::cmd("reset --hard".to_string(), &git_dir)?;
+            return Ok(false);
+        }
+
+        if !Git::get_session(&git_dir)?.unmerged_paths.is_empty() {
+            return Err("Conflict not resolved".red().into());
+        }
+    }
+
+    // The message keeps the messages of both upstream commits
+    let msg_target = Git::cmd(format!("log -1 --format=%B {}", target_backport), &git_dir)?;
+    let msg_upstream = Git::cmd(format!("log -1 --format=%B {}", upstream), &git_dir)?;
+    let mut msg_upstream = message::Message::parse(&msg_upstream);
+
+    // Without a reference the squashed commit would be lost, so plain messages get one too
+    let style = match message::MessageStyle::from_options(options) {
+        message::MessageStyle::Plain => message::MessageStyle::CherryPick,
+        style => style,
+    };
+    message::add_reference(&style, upstream, &mut msg_upstream);
+
+    // The backporter signs off once for the combined commit
+    let signoff = if options.signoff { Some(signoff_line(options)?) } else { None };
+    let temp_file = Temp::new_file()?;
+    let msg_file = temp_file.to_path_buf().display().to_string();
+
+    let (config, args) = commit_args(options, upstream)?;
+
+    let head = Git::get_last_commit(&git_dir)?;
+    if head == target_backport {
+        let mut msg_target = message::Message::parse(&msg_target);
+        if let Some(signoff) = &signoff {
+            // Move the sign-off of the target below both messages. Only the trailer block of
+            // the target is ours, the upstream message keeps its lines
+            let mut paragraphs: Vec<String> = msg_target.body.split("\n\n").map(|p| p.to_string()).collect();
+            if let Some(trailers) = paragraphs.last_mut() {
+                *trailers = trailers.split("\n").filter(|l| l.trim() != signoff).collect::<Vec<&str>>().join("\n");
+            }
+            msg_target.body = paragraphs.into_iter().filter(|p| !p.trim().is_empty()).collect::<Vec<String>>().join("\n\n");
+        }
+
+        let mut msg = message::Message::parse(&format!("{}\n\n{}", msg_target.text().trim_end(), msg_upstream.text()));
+        if let Some(signoff) = &signoff {
+            msg.add_trailer(signoff);
+        }
+        fs::write(&temp_file, msg.text())?;
+        Git::cmd(format!("{}commit --amend --no-verify {}-F {}", config, args, msg_file), &git_dir)?;
+        let new_hash = Git::get_last_commit(&git_dir)?;
+        log.commit_update(target, &new_hash)?;
+        log.commit_update(upstream, "squashed")?;
+    } else {
+        // The target is further down the branch so let a rebase squash it in
+        if let Some(signoff) = &signoff {
+            if !message::Message::parse(&msg_target).contains(signoff) && !msg_upstream.contains(signoff) {
+                msg_upstream.add_trailer(signoff);
+            }
+        }
+        fs::write(&temp_file, msg_upstream.text())?;
+        Git::cmd(format!("{}commit --no-verify {}-F {}", config, args, msg_file), &git_dir)?;
+        let new_hash = Git::get_last_commit(&git_dir)?;
+        log.commit_update(upstream, &new_hash)?;
+        cmd_rebase(options, log)?;
+    }
+
+    // The backport of target now carries the changes of both commits
+    let backport = log.get_all()?.into_iter().find(|c| c.0 == target).map(|c| c.1).unwrap_or_default();
+    if backport.len() == 40 {
+        match compare_commits(options, target, &backport) {
+            Ok(res) => log.set_annotation(target, "compare", &format!("{:?}", res))?,
+            Err(e) => println!("{} {}: {}", "Failed to compare with".yellow(), target, e),
+        }
+    }
+
+    println!("{} {}", "Squashed into".green(), target);
+
+    Ok(true)
+}
+
 pub fn cmd_apply(options: &Options, log: &mut Log) -> Result<(), Box<dyn Error>> {
     let git_dir = options.git_dir.clone().unwrap();
     let log_read = log.clone();
     let mut i: u32 = log_read.next_index();
     let num_commits = log_read.num_commits()?;
 
-    let cherrypick_cache = get_cherrypick_cache(options)?;
+    let mut cherrypick_cache = get_cherrypick_cache(options)?;
     let commit_cache = get_commit_cache(options)?;
+    let file_cache = match partial_threshold(options) {
+        Some(_) => get_file_cache(options)?,
+        None => vec![],
+    };
+    let mut patch_cache = PatchCache::new();
+
+    if let Some(flavor) = flavor::from_options(options)? {
+        cherrypick_cache.extend(flavor.picks(options)?);
+    }
+
+    // Commits that failed verification must be fixed before we continue
+    verify::recheck(options, log)?;
+
+    // Upstream commits added to the branch since the last verification
+    let mut unverified: Vec<String> = vec![];
+    let mut last_head = Git::get_last_commit(&git_dir)?;
+    let mut last_hash = log.next_commit().to_string();
 
     // After this call the tree should be clean and ready to enter the apply loop
     handle_git_state(options, log)?;
 
     loop {
+        let head = Git::get_last_commit(&git_dir)?;
+        if head != last_head {
+            unverified.push(last_hash.clone());
+            last_head = head;
+        }
+        verify::check(options, log, &mut unverified, false)?;
+
         let log_read = log.clone();
         let next_hash = log_read.next_commit();
         let commit = Git::show(next_hash, &git_dir)?;
 
         if next_hash.is_empty() {
+            verify::check(options, log, &mut unverified, true)?;
             break Ok(());
         }
+        last_hash = next_hash.to_string();
 
         println!("{} {}/{}: {} {}", "Applying".green(), i, num_commits, next_hash, commit.subject);
         i += 1;
@@ -320,13 +835,42 @@ pub fn cmd_apply(options: &Options, log: &mut Log) -> Result<(), Box<dyn Error>>
             continue;
         }
 
+        hooks::check(options, log, Hook::PreApply, next_hash)?;
+
+        // Commits annotated with squash-into are folded into the backport of their target
+        if let Some(target) = log.get_annotation(next_hash, "squash-into") {
+            let target_status = log.get_all()?.into_iter().find(|c| c.0 == target).map(|c| c.1);
+
+            match target_status {
+                Some(status) if status.len() == 40 => {
+                    // The squashed commit has no backport of its own, so the amended target is
+                    // verified instead
+                    if apply_squash(options, log, next_hash, &target)? {
+                        last_hash = target.clone();
+                    }
+                    continue;
+                },
+                Some(status) if status.is_empty() => {
+                    return Err(format!("Cannot squash {} into {} which is not applied yet", next_hash, target).red().into());
+                },
+                _ => {
+                    // Without a backported target the commit is applied on its own
+                    println!("{} {}", "Target is not backported. Applying instead of squashing into:".yellow(), target);
+                    log.set_annotation(next_hash, "squash-into", "")?;
+                },
+            }
+        }
+
         // Apply commit
-        let res = Git::cmd(format!("cherry-pick {} > /dev/null", next_hash), &git_dir);
+        let mut args = String::new();
+        if message::MessageStyle::from_options(options) == message::MessageStyle::CherryPick {
+            args.push_str("-x ");
+        }
+        let res = Git::cmd(format!("cherry-pick {}{} > /dev/null", args, next_hash), &git_dir);
 
         match res {
             Ok(_) => {
-                let new_hash = Git::get_last_commit(&git_dir)?;
-                log.commit_update(next_hash, &new_hash)?;
+                finish_pick(options, log, next_hash)?;
             },
             Err(_) => {
                 // If apply fails, check for duplicates (commits WITHOUT cherry pick tag)
@@ -335,8 +879,15 @@ pub fn cmd_apply(options: &Options, log: &mut Log) -> Result<(), Box<dyn Error>>
                 for cache_item in commit_cache.iter() {
                     // Do a quick compare on subject to avoid the costly compare_commits() call.
                     if commit.subject == cache_item.1 {
-                        let res = compare_commits(options, &commit.hash, &cache_item.0)?;
-                        if res >= CompareResult::Similar {
+                        // A commit we fail to compare is just not considered a duplicate
+                        let res = match compare_commits(options, &commit.hash, &cache_item.0) {
+                            Ok(res) => res,
+                            Err(e) => {
+                                println!("{} {}: {}", "Failed to compare with".yellow(), cache_item.0, e);
+                                continue;
+                            },
+                        };
+                        if res >= CompareResult::Equivalent {
                             println!("{} {}", "Found duplicate:".yellow(), cache_item.0);
                             is_duplicate = true;
                             log.commit_update(next_hash, format!("duplicate {}", cache_item.0).as_str())?;
@@ -350,6 +901,17 @@ pub fn cmd_apply(options: &Options, log: &mut Log) -> Result<(), Box<dyn Error>>
                     continue;
                 }
 
+                // Partial backports are left for the user to resolve but flagged in the log. They
+                // often get a new subject, so any commit touching the same files is compared
+                if let Some(threshold) = partial_threshold(options) {
+                    if let Some((hash, score)) = best_partial(options, &commit.hash, &file_cache, &mut patch_cache) {
+                        if score >= threshold {
+                            println!("{} {} ({:.0}%)", "Partially present in:".yellow(), hash, score * 100.0);
+                            log.set_annotation(next_hash, "partial", &format!("{} {:.0}%", hash, score * 100.0))?;
+                        }
+                    }
+                }
+
                 if !handle_git_state(options, log)? {
                     i -= 1;
                 }
@@ -437,13 +999,34 @@ pub fn cmd_edit(options: &Options, log: &mut Log) -> Result<bool, Box<dyn Error>
         let commit_file = format!("/tmp/{}.patch", commit);
         let target_file = format!("/tmp/{}-{}", range_stop, file_path.file_name().unwrap().to_str().unwrap());
 
+        // A resolution from before a goto might be available for this commit
+        let previous = log.get_annotation(commit, "previous");
+
         loop {
-            let ask = Util::ask(format!("Edit {} (Y)es/(n)o)/(v)iew commit/(s)kip commit/(a)bort? ", file.bold()), vec!["y", "n", "v", "s", "a"], "y");
+            let ask = if previous.is_some() {
+                Util::ask(format!("Edit {} (Y)es/(n)o)/(v)iew commit/(p)revious resolution/(s)kip commit/(a)bort? ", file.bold()), vec!["y", "n", "v", "p", "s", "a"], "y")
+            } else {
+                Util::ask(format!("Edit {} (Y)es/(n)o)/(v)iew commit/(s)kip commit/(a)bort? ", file.bold()), vec!["y", "n", "v", "s", "a"], "y")
+            };
             let val = ask.as_str();
 
             match val {
                 "n" => break,
                 "a" => return Err("Aborted by user".red().into()),
+                "p" => {
+                    let previous = previous.clone().unwrap();
+                    match Git::cmd(format!("checkout {} -- {}", previous, file), &git_dir) {
+                        Err(_error) => println!("File {} doesn't exist in previous resolution", file.red()),
+                        _ => println!("Restored {} from previous resolution {}", file, previous),
+                    }
+                    let lineno = find_conflict_lineno(format!("{}/{}", git_dir, file))?;
+                    if lineno == "0" {
+                        Git::cmd(format!("add {file}"), &git_dir)?;
+                        break;
+                    }
+                    println!("{}", "File still contains conflics!".red());
+                    continue
+                },
                 "v" => {
                     Command::new("sh")
                         .arg("-c")
@@ -511,20 +1094,20 @@ pub fn cmd_edit(options: &Options, log: &mut Log) -> Result<bool, Box<dyn Error>
 
 pub fn cmd_status(options: &Options, log: &Log) -> Result<(), Box<dyn Error>> {
     let git_dir = options.git_dir.clone().unwrap();
-    let branch = options.branch.clone().unwrap();
-    let paths = options.paths.clone().unwrap();
-    let range_stop = options.range_stop.clone().unwrap();
 
     let next_index = log.next_index();
     let num_commits = log.num_commits()?;
     let percentage: f32 = ((next_index as f32) / (num_commits as f32)) * 100.0;
     println!("Progress {:.0}% ({}/{})", percentage, next_index, num_commits);
 
-    let stdout = Git::cmd(format!("diff --stat {branch} {range_stop} -- {paths}"), &git_dir)?;
-    let lines: Vec<&str> = stdout.split("\n").collect();
-    let summary = lines[lines.len() - 2].trim();
+    let (stat, hidden) = remaining_stat(options)?;
+    let summary = stat.last().map(|l| l.trim()).unwrap_or_default();
 
-    println!("{summary}\n");
+    println!("{summary}");
+    if let Some(note) = expected::hidden_note(hidden) {
+        println!("{note}");
+    }
+    println!();
 
     let session = Git::get_session(&git_dir)?;
 
@@ -536,10 +1119,66 @@ pub fn cmd_status(options: &Options, log: &Log) -> Result<(), Box<dyn Error>> {
 
     print_session(&git_dir)?;
 
+    let deviations = log.get_all()?.iter()
+        .filter(|c| log.get_annotation(&c.0, "compare").is_some_and(|r| is_deviation(&r)))
+        .count();
+    if deviations > 0 {
+        println!("{}", format!("\n{} backports deviate from upstream. Run deviations to list them", deviations).yellow());
+    }
+
     if next_index < num_commits {
         let next_commit = log.next_commit();
         let commit = Git::show(next_commit, &git_dir)?;
         println!("\nNext commit to apply:\n{} {}", commit.hash, commit.subject);
+
+        if let Some(partial) = log.get_annotation(next_commit, "partial") {
+            println!("{} {}", "Partially present in:".yellow(), partial);
+        }
+    }
+
+    Ok(())
+}
+
+// Returns true if a stored compare result means the backport differs from upstream
+pub fn is_deviation(res: &str) -> bool {
+    res != "Same" && res != "Identical"
+}
+
+pub fn cmd_deviations(options: &Options, log: &mut Log) -> Result<(), Box<dyn Error>> {
+    let git_dir = options.git_dir.clone().unwrap();
+    let commits = log.get_all()?;
+    let mut found = 0;
+
+    for (i, (upstream, backport)) in commits.iter().enumerate() {
+        if backport.len() != 40 {
+            continue;
+        }
+
+        // Backports applied before results were stored are compared now
+        let res = match log.get_annotation(upstream, "compare") {
+            Some(res) => res,
+            None => match compare_commits(options, upstream, backport) {
+                Ok(res) => {
+                    let res = format!("{:?}", res);
+                    log.set_annotation(upstream, "compare", &res)?;
+                    res
+                },
+                // Shown as unknown and compared again next time
+                Err(_) => "Unknown".to_string(),
+            },
+        };
+
+        if !is_deviation(&res) {
+            continue;
+        }
+
+        let commit = Git::show(upstream, &git_dir)?;
+        println!("{}: {} {} {} {}", i + 1, &upstream[..12], &backport[..12], res.yellow(), commit.subject);
+        found += 1;
+    }
+
+    if found == 0 {
+        println!("No backports deviate from upstream");
     }
 
     Ok(())
@@ -558,23 +1197,69 @@ pub fn cmd_restart(options: &Options, log: &mut Log) -> Result<(), Box<dyn Error
     println!("Reseting...");
     Git::cmd(format!("reset --hard {}", branch_point), &git_dir)?;
 
-    let mut commits = String::from("");
-    let lines: Vec<&str> = log.commits.split("\n").collect();
+    log.reset_entries(None)
+}
 
-    for line in lines {
-        let cols: Vec<&str> = line.trim().split(" ").collect();
+// Restart the backport from an arbitrary commit in the log while keeping everything before it
+pub fn cmd_goto(options: &Options, log: &mut Log) -> Result<(), Box<dyn Error>> {
+    let git_dir = options.git_dir.clone().unwrap();
+    let branch_point = options.branch_point.clone().unwrap();
+    let session = Git::get_session(&git_dir)?;
 
-        if cols.len() >= 2 && cols[0].len() == 40 {
-            commits.push_str(cols[0]);
-            commits.push('\n');
-        } else {
-            commits.push_str(line);
-            commits.push('\n');
+    let target = match &options.hash {
+        Some(arg) => arg.trim().to_string(),
+        None => return Err("No --hash was provided".red().into()),
+    };
+
+    if session.state != GitSessionState::None {
+        return Err("Invalid session state. Check your git repo.".red().into());
+    }
+
+    // The target can be specified with either its upstream or backported hash
+    let commits = log.get_all()?;
+    let index = commits.iter().position(|c| c.0 == target || c.1 == target);
+    let index = match index {
+        Some(i) => i,
+        None => return Err(format!("Hash {} not found in log", target).red().into()),
+    };
+    let upstream = commits[index].0.clone();
+
+    // Find the backported commit preceding the target
+    let mut reset_point = branch_point;
+    for commit in commits[..index].iter() {
+        if commit.1.len() == 40 {
+            reset_point = commit.1.clone();
         }
     }
 
-    log.commits = commits;
-    log.save()?;
+    let subject = Git::show(&upstream, &git_dir)?.subject;
+    println!("Restarting from {} {}", upstream, subject);
+    println!("Branch will be reset to {}", reset_point);
+
+    let val = Util::ask("All progress from this commit and onward will be deleted.\nAre you sure? (y)es/(N)o: ".to_string(), vec!["y", "n"], "n");
+
+    if val != "y" {
+        return Ok(());
+    }
+
+    // Keep the old backports reachable and remember them so their resolutions can be reused
+    if options.keep_resolutions {
+        let head = Git::get_last_commit(&git_dir)?;
+        Git::cmd(format!("update-ref refs/b2tf/previous/{} {}", head, head), &git_dir)?;
+
+        for commit in commits[index..].iter() {
+            if commit.1.len() == 40 {
+                log.set_annotation(&commit.0, "previous", &commit.1)?;
+            }
+        }
+        println!("Previous resolutions kept in refs/b2tf/previous/{}", head);
+    }
+
+    println!("Reseting...");
+    Git::cmd(format!("reset --hard {}", reset_point), &git_dir)?;
+
+    log.reset_entries(Some(&upstream))?;
+
     Ok(())
 }
 
@@ -583,12 +1268,13 @@ pub fn cmd_skip(options: &Options, log: &mut Log) -> Result<(), Box<dyn Error>>
     let log_read = log.clone();
 
     let session = Git::get_session(&git_dir)?;
+    let next_commit = log_read.next_commit();
+    hooks::check(options, log, Hook::OnSkip, next_commit)?;
 
     if session.state == GitSessionState::Cherrypick {
         Git::cmd("cherry-pick --abort".to_string(), &git_dir)?;
     }
 
-    let next_commit = log_read.next_commit();
     log.commit_update(next_commit, "skip")?;
 
     println!("Skipped {next_commit}.");
@@ -596,52 +1282,86 @@ pub fn cmd_skip(options: &Options, log: &mut Log) -> Result<(), Box<dyn Error>>
     Ok(())
 }
 
-fn print_hunk(hunk: &Hunk) {
-    print!("{}", format!("@@ -{},{} ", hunk.source_start, hunk.source_length).cyan());
-    print!("{}", format!("+{},{} @@ ", hunk.target_start, hunk.target_length).cyan());
-    println!("{}", hunk.section_header);
-    for line in hunk.clone() {
-        if line.line_type == "+" {
-            println!("{} {}", line.line_type.green(), line.value.green());
-        } else if line.line_type == "-" {
-            println!("{} {}", line.line_type.red(), line.value.red());
-        } else {
-            println!("{} {}", line.line_type, line.value);
-        }
-    }
-}
+pub fn cmd_diff(options: &Options) -> Result<(), Box<dyn Error>> {
+    let (patch, hidden) = expected::remaining_diff(options)?;
+    let mut r = Renderer::new(options);
 
-fn print_patched_file_header(file: &PatchedFile) {
-    println!("--- {}", file.source_file);
-    println!("+++ {}", file.target_file);
-}
+    for file in &patch.files {
+        r.file_header(file);
 
-fn print_patched_file(file: &PatchedFile) {
-    print_patched_file_header(&file);
+        for hunk in &file.hunks {
+            r.hunk(hunk);
+            r.line(&format!("# hunk {}", hunk.fingerprint()).bright_blue().to_string());
+        }
+    }
 
-    for hunk in file.clone() {
-        print_hunk(&hunk);
+    if let Some(note) = expected::hidden_note(hidden) {
+        r.line(&note);
     }
+
+    r.finish()
 }
 
-pub fn cmd_diff(options: &Options) -> Result<(), Box<dyn Error>> {
+// Returns the upstream commits in range-start..range-stop that last touched the lines of hunk
+fn blame_hunk(options: &Options, file: &PatchFile, hunk: &PatchHunk) -> Result<Vec<String>, Box<dyn Error>> {
     let git_dir = options.git_dir.clone().unwrap();
-    let branch = options.branch.clone().unwrap();
+    let range_start = options.range_start.clone().unwrap();
     let range_stop = options.range_stop.clone().unwrap();
-    let paths = options.paths.clone().unwrap();
 
-    let stdout = Git::cmd(format!("diff {branch} {range_stop} -- {paths}").to_string(), &git_dir)?;
-    let mut patch = PatchSet::new();
-    patch.parse(stdout).expect("Error parsing diff");
+    if file.target_file == "/dev/null" {
+        return Ok(vec![]);
+    }
 
-    for file in patch {
-        print_patched_file(&file);
+    // Prefer the lines range-stop added, otherwise look at the whole hunk
+    let mut lines: Vec<usize> = hunk.lines.iter()
+        .filter(|l| l.line_type == "+")
+        .filter_map(|l| l.target_line_no)
+        .collect();
+    if lines.is_empty() {
+        lines = hunk.lines.iter().filter_map(|l| l.target_line_no).collect();
+    }
+    if lines.is_empty() {
+        return Ok(vec![]);
     }
 
-    Ok(())
+    let ranges: Vec<String> = lines.iter().map(|l| format!("-L {},{}", l, l)).collect();
+    let path = PatchFile::path(&file.target_file);
+    let stdout = Git::cmd(format!("blame -l -s {} {}..{} -- {}", ranges.join(" "), range_start, range_stop, path), &git_dir)?;
+
+    // Boundary commits are prefixed with ^ and come from before range-start
+    let mut commits: Vec<String> = vec![];
+    for line in stdout.split("\n") {
+        if line.len() < 40 || line.starts_with("^") {
+            continue;
+        }
+        let hash = line[..40].to_string();
+        if !commits.contains(&hash) {
+            commits.push(hash);
+        }
+    }
+
+    Ok(commits)
+}
+
+// Describe the status of an upstream commit in the log
+fn describe_status(log: &Log, hash: &str) -> Result<String, Box<dyn Error>> {
+    let status = match log.get_all()?.into_iter().find(|c| c.0 == hash) {
+        Some((_, status)) => status,
+        None => return Ok("not in log".to_string()),
+    };
+
+    let desc = if status.is_empty() {
+        "pending".to_string()
+    } else if status.len() == 40 {
+        format!("applied as {}", &status[..12])
+    } else {
+        status
+    };
+
+    Ok(desc)
 }
 
-pub fn cmd_diffdiff(options: &Options) -> Result<(), Box<dyn Error>> {
+pub fn cmd_diffdiff(options: &Options, log: &Log) -> Result<(), Box<dyn Error>> {
     let git_dir = options.git_dir.clone().unwrap();
     let branch = options.branch.clone().unwrap();
     let branch_point = options.branch_point.clone().unwrap();
@@ -661,10 +1381,10 @@ pub fn cmd_diffdiff(options: &Options) -> Result<(), Box<dyn Error>> {
     let diff_stop = Git::cmd(format!("diff {branch} {range_stop} -- {paths}").to_string(), &git_dir)?;
 
     let mut patch_start = Patch::new();
-    patch_start.parse(&diff_start);
+    patch_start.parse(&diff_start)?;
 
     let mut patch_stop = Patch::new();
-    patch_stop.parse(&diff_stop);
+    patch_stop.parse(&diff_stop)?;
 
     patch_stop.subtract(patch_start, true);
 
@@ -674,24 +1394,194 @@ pub fn cmd_diffdiff(options: &Options) -> Result<(), Box<dyn Error>> {
         }
         let diff_skip = Git::cmd(format!("diff {s}~1..{s}").to_string(), &git_dir)?;
         let mut patch_skip = Patch::new();
-        patch_skip.parse(&diff_skip);
+        patch_skip.parse(&diff_skip)?;
         patch_stop.subtract(patch_skip, true);
     }
 
-    patch_stop.print();
+    let hidden = Expected::load(options)?.apply(options, &mut patch_stop)?;
 
-    Ok(())
+    let mut r = Renderer::new(options);
+
+    // Show which upstream commits are responsible for each residual hunk
+    for file in &patch_stop.files {
+        r.file_header(file);
+
+        for hunk in &file.hunks {
+            r.hunk(hunk);
+            r.line(&format!("# hunk {}", hunk.fingerprint()).bright_blue().to_string());
+
+            // Files renamed or deleted within the range can't always be blamed
+            let hashes = match blame_hunk(options, file, hunk) {
+                Ok(hashes) => hashes,
+                Err(_) => {
+                    r.line(&"# Unable to attribute the hunk".bright_blue().to_string());
+                    vec![]
+                },
+            };
+            for hash in hashes {
+                let commit = Git::show(&hash, &git_dir)?;
+                let status = describe_status(log, &hash)?;
+                r.line(&format!("# {} ({}) {}", &hash[..12], status, commit.subject).bright_blue().to_string());
+            }
+        }
+    }
+
+    if let Some(note) = expected::hidden_note(hidden) {
+        r.line(&note);
+    }
+
+    r.finish()
 }
 
-pub fn cmd_diffstat(options: &Options) -> Result<(), Box<dyn Error>> {
+// Render the changes only found in one of the patches
+fn render_interdiff(r: &mut Renderer, patch1: &Patch, patch2: &Patch, name1: &str, name2: &str) {
+    let mut missing = patch1.clone();
+    missing.subtract(patch2.clone(), true);
+    let mut added = patch2.clone();
+    added.subtract(patch1.clone(), true);
+
+    if !missing.files.is_empty() {
+        r.line(&format!("Only in {}:", name1).bright_blue().to_string());
+        r.patch(&missing);
+    }
+    if !added.files.is_empty() {
+        r.line(&format!("Only in {}:", name2).bright_blue().to_string());
+        r.patch(&added);
+    }
+}
+
+pub fn cmd_range_diff(options: &Options, log: &Log) -> Result<(), Box<dyn Error>> {
+    let git_dir = options.git_dir.clone().unwrap();
+    let commits = log.get_all()?;
+    let mut shown = 0;
+    let mut r = Renderer::new(options);
+
+    for (i, (upstream, backport)) in commits.iter().enumerate() {
+        if backport.len() != 40 {
+            continue;
+        }
+
+        let commit = Git::show(upstream, &git_dir)?;
+        let patches = commit_patch(options, upstream).and_then(|u| Ok((u, commit_patch(options, backport)?)));
+
+        // Entries that can't be parsed are listed as unknown
+        let (patch_upstream, patch_backport) = match patches {
+            Ok(patches) => patches,
+            Err(_) => {
+                r.line(&format!("{}: {} = {} {} (Unknown)", i + 1, &upstream[..12], &backport[..12],
+                                commit.subject).yellow().to_string());
+                r.line("");
+                shown += 1;
+                continue;
+            },
+        };
+
+        let res = patch_upstream.compare(patch_backport.clone());
+        if res >= CompareResult::Same && !options.show_all {
+            continue;
+        }
+
+        let score = patch_upstream.similarity(&patch_backport).score();
+
+        r.line(&format!("{}: {} = {} {} ({:?}, {:.0}%)", i + 1, &upstream[..12], &backport[..12],
+                        commit.subject, res, score * 100.0).yellow().to_string());
+
+        // Changes that upstream has but the backport doesn't, and the other way around
+        render_interdiff(&mut r, &patch_upstream, &patch_backport, "upstream", "backport");
+        r.line("");
+
+        shown += 1;
+    }
+
+    if shown == 0 {
+        r.line("All backports are the same as upstream");
+    }
+
+    r.finish()
+}
+
+// Returns the diffstat of patch in the format of git diff --stat
+fn diffstat(patch: &Patch) -> Vec<String> {
+    let mut lines = vec![];
+    let (mut insertions, mut deletions) = (0, 0);
+
+    let stats: Vec<(String, usize, usize, bool)> = patch.files.iter().map(|f| {
+        let source = PatchFile::path(&f.source_file);
+        let target = PatchFile::path(&f.target_file);
+        let path = match f.operation {
+            FileOperation::Delete => source.to_string(),
+            FileOperation::Rename | FileOperation::Copy => format!("{} => {}", source, target),
+            _ => target.to_string(),
+        };
+        let changed: Vec<&str> = f.hunks.iter().flat_map(|h| h.lines.iter()).map(|l| l.line_type.as_str()).collect();
+        let added = changed.iter().filter(|t| **t == "+").count();
+        let removed = changed.iter().filter(|t| **t == "-").count();
+        (path, added, removed, f.binary)
+    }).collect();
+
+    let width = stats.iter().map(|s| s.0.len()).max().unwrap_or(0);
+    let most = stats.iter().map(|s| s.1 + s.2).max().unwrap_or(0);
+    let digits = most.to_string().len();
+
+    for (path, added, removed, binary) in &stats {
+        if *binary {
+            lines.push(format!(" {:width$} | Bin", path));
+            continue;
+        }
+
+        // Scale the graph down like git does for large changes
+        let scale = |n: usize| if most > 50 { (n * 50).div_ceil(most) } else { n };
+        lines.push(format!(" {:width$} | {:>digits$} {}{}", path, added + removed,
+                           "+".repeat(scale(*added)).green(), "-".repeat(scale(*removed)).red()));
+        insertions += added;
+        deletions += removed;
+    }
+
+    if stats.is_empty() {
+        return lines;
+    }
+
+    let plural = |n: usize, word: &str| if n == 1 { format!("{} {}", n, word) } else { format!("{} {}s", n, word) };
+    let mut summary = format!(" {} changed", plural(stats.len(), "file"));
+    if insertions > 0 {
+        summary.push_str(&format!(", {}(+)", plural(insertions, "insertion")));
+    }
+    if deletions > 0 {
+        summary.push_str(&format!(", {}(-)", plural(deletions, "deletion")));
+    }
+    lines.push(summary);
+
+    lines
+}
+
+// Returns the diffstat between the branch and range-stop without the expected differences, and
+// the number of hunks that were hidden. The diff is only parsed if there is something to hide
+fn remaining_stat(options: &Options) -> Result<(Vec<String>, usize), Box<dyn Error>> {
     let git_dir = options.git_dir.clone().unwrap();
     let branch = options.branch.clone().unwrap();
     let range_stop = options.range_stop.clone().unwrap();
     let paths = options.paths.clone().unwrap();
 
-    let stdout = Git::cmd(format!("diff --stat {branch} {range_stop} -- {paths}").to_string(), &git_dir)?;
+    if Expected::load(options)?.is_empty() {
+        let stdout = Git::cmd(format!("diff --stat {branch} {range_stop} -- {paths}"), &git_dir)?;
+        return Ok((stdout.trim_end().split("\n").map(|l| l.to_string()).collect(), 0));
+    }
 
-    println!("{stdout}");
+    let (patch, hidden) = expected::remaining_diff(options)?;
+
+    Ok((diffstat(&patch), hidden))
+}
+
+pub fn cmd_diffstat(options: &Options) -> Result<(), Box<dyn Error>> {
+    let (stat, hidden) = remaining_stat(options)?;
+
+    for line in stat {
+        println!("{line}");
+    }
+
+    if let Some(note) = expected::hidden_note(hidden) {
+        println!("{note}");
+    }
 
     Ok(())
 }
@@ -709,25 +1599,35 @@ pub fn cmd_rebase(options: &Options, log: &mut Log) -> Result<(), Box<dyn Error>
 
     // Generate a pick-file to feed into git-rebase
     if session.state == GitSessionState::None {
-        for hash in commits {
+        for hash in commits.iter() {
             let mut picked_hash = &hash.0;
 
             if hash.1.len() == 40 {
                 // If the commit is already backported we pick that hash
                 picked_hash = &hash.1;
-            } else if hash.1.len() > 0 {
+            } else if !hash.1.is_empty() {
                 // Skip all empty/duplicates/cherry-picks etc.
                 continue;
             }
 
-            file.write_all(format!("pick {}\n", picked_hash).as_bytes())?;
+            // Backports to squash are picked together with their target
+            if log.get_annotation(&hash.0, "squash-into").is_none() {
+                file.write_all(format!("pick {}\n", picked_hash).as_bytes())?;
+
+                for squash in commits.iter() {
+                    if squash.1.len() == 40 && log.get_annotation(&squash.0, "squash-into").as_ref() == Some(&hash.0) {
+                        file.write_all(format!("squash {}\n", squash.1).as_bytes())?;
+                    }
+                }
+            }
 
             if hash.0 == last_commit {
                 break;
             }
         }
 
-        let query = format!("GIT_SEQUENCE_EDITOR='cp {} ' git -C {} rebase -i {}", filename, git_dir, branch_point);
+        // Squashed commits keep the messages of both commits without asking
+        let query = format!("GIT_SEQUENCE_EDITOR='cp {} ' GIT_EDITOR=true git -C {} rebase -i {}", filename, git_dir, branch_point);
 
         let status = Command::new("sh")
             .arg("-c")
@@ -761,6 +1661,185 @@ pub fn cmd_rebase(options: &Options, log: &mut Log) -> Result<(), Box<dyn Error>
     Ok(())
 }
 
+// Returns a short description of the state of a log entry
+fn describe_entry(status: &str) -> String {
+    if status.is_empty() {
+        return "pending".to_string();
+    }
+    if status.len() == 40 {
+        return format!("applied {}", &status[..12]);
+    }
+    status.to_string()
+}
+
+// Parse and validate an edited list. Returns a vector of (command, upstream hash)
+fn parse_edit_list(contents: &str, log: &Log) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let commits = log.get_all()?;
+    let mut list: Vec<(String, String)> = vec![];
+
+    for line in contents.split("\n") {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("#") {
+            continue;
+        }
+
+        let cols: Vec<&str> = line.split_whitespace().collect();
+        if cols.len() < 2 || cols[1].len() != 40 {
+            return Err(format!("Invalid line: {}", line).into());
+        }
+
+        let cmd = match cols[0] {
+            "p" | "pick" => "pick",
+            "d" | "drop" => "drop",
+            "s" | "squash" => "squash",
+            _ => return Err(format!("Unknown command: {}", cols[0]).into()),
+        };
+        let hash = cols[1].to_string();
+
+        if !commits.iter().any(|c| c.0 == hash) {
+            return Err(format!("Hash {} is not in the log", hash).into());
+        }
+        if list.iter().any(|l| l.1 == hash) {
+            return Err(format!("Hash {} is listed more than once", hash).into());
+        }
+        list.push((cmd.to_string(), hash));
+    }
+
+    for commit in commits.iter() {
+        if !list.iter().any(|l| l.1 == commit.0) {
+            return Err(format!("Hash {} is missing. Use drop to remove entries", commit.0).into());
+        }
+    }
+
+    let mut pending = String::new();
+    let mut target = String::new();
+
+    for (cmd, hash) in list.iter() {
+        let status = &commits.iter().find(|c| c.0 == *hash).unwrap().1;
+
+        if cmd == "drop" {
+            continue;
+        }
+
+        if cmd == "squash" {
+            if target.is_empty() {
+                return Err(format!("Cannot squash {} without a preceding pick", hash).into());
+            }
+            let target_status = &commits.iter().find(|c| c.0 == target).unwrap().1;
+            if status.len() == 40 && target_status.len() != 40 {
+                return Err(format!("Cannot squash applied {} into pending {}", hash, target).into());
+            }
+            if status.len() != 40 && !status.is_empty() {
+                return Err(format!("Cannot squash {} with status: {}", hash, status).into());
+            }
+            continue;
+        }
+
+        // Applied commits must stay in front of the pending ones since they are already on the branch
+        if status.is_empty() && pending.is_empty() {
+            pending = hash.clone();
+        }
+        if status.len() == 40 && !pending.is_empty() {
+            return Err(format!("Applied commit {} cannot be moved after pending commit {}", hash, pending).into());
+        }
+
+        if status.len() == 40 || status.is_empty() {
+            target = hash.clone();
+        } else {
+            target.clear();
+        }
+    }
+
+    Ok(list)
+}
+
+// Reorder, drop and squash log entries through an editor
+pub fn cmd_edit_list(options: &Options, log: &mut Log) -> Result<(), Box<dyn Error>> {
+    let git_dir = options.git_dir.clone().unwrap();
+    let session = Git::get_session(&git_dir)?;
+    let commits = log.get_all()?;
+    let temp_file = Temp::new_file()?;
+    let pathbuf = temp_file.to_path_buf();
+    let filename = pathbuf.as_os_str().to_str().unwrap();
+
+    if session.state != GitSessionState::None {
+        return Err("Invalid session state. Check your git repo.".red().into());
+    }
+
+    let mut contents = String::new();
+    for commit in commits.iter() {
+        let subject = Git::show(&commit.0, &git_dir)?.subject;
+        contents.push_str(format!("pick {} # {}: {}\n", commit.0, describe_entry(&commit.1), subject).as_str());
+    }
+    contents.push_str("\n\
+        # Commands:\n\
+        # p, pick <hash> = keep entry\n\
+        # d, drop <hash> = remove entry from the log and the branch\n\
+        # s, squash <hash> = fold entry into the preceding picked entry\n\
+        #\n\
+        # Entries can be moved by reordering the lines.\n\
+        # Applied entries must stay in front of the pending ones.\n");
+    fs::write(filename, contents)?;
+
+    let list = loop {
+        Command::new("sh")
+            .arg("-c")
+            .arg(format!("${{EDITOR:-vim}} {}", filename))
+            .status()
+            .expect("Failed to open editor");
+
+        match parse_edit_list(&fs::read_to_string(filename)?, log) {
+            Ok(list) => break list,
+            Err(error) => {
+                println!("{}", error.to_string().red());
+                let ask = Util::ask("(E)dit again/(a)bort? ".to_string(), vec!["e", "a"], "e");
+                if ask == "a" {
+                    return Err("Aborted by user".red().into());
+                }
+            },
+        }
+    };
+
+    // Check if the branch needs to be rebased
+    let applied_before: Vec<&String> = commits.iter().filter(|c| c.1.len() == 40).map(|c| &c.0).collect();
+    let applied_after: Vec<&String> = list.iter().filter(|l| {
+        l.0 == "pick" && commits.iter().any(|c| c.0 == l.1 && c.1.len() == 40)
+    }).map(|l| &l.1).collect();
+    let squashing = list.iter().any(|l| {
+        l.0 == "squash" && commits.iter().any(|c| c.0 == l.1 && c.1.len() == 40)
+    });
+    let needs_rebase = squashing || applied_before != applied_after;
+
+    // Write the new log
+    let blocks = log.get_blocks();
+    let mut new_blocks: Vec<String> = vec![];
+    for (cmd, hash) in list.iter() {
+        if cmd == "drop" {
+            println!("Dropping {}", hash);
+            continue;
+        }
+        let block = blocks.iter().find(|b| b.0 == *hash).unwrap();
+        new_blocks.push(block.1.clone());
+    }
+    log.set_blocks(&new_blocks)?;
+
+    let mut target = String::new();
+    for (cmd, hash) in list.iter() {
+        if cmd == "pick" {
+            target = hash.clone();
+        } else if cmd == "squash" {
+            println!("Squashing {} into {}", hash, target);
+            log.set_annotation(hash, "squash-into", &target)?;
+        }
+    }
+
+    if needs_rebase {
+        cmd_rebase(options, log)?;
+    }
+
+    Ok(())
+}
+
 // This functions will update log with hashes added during a rebase
 // It starts updating from the first commit and stops when the subjects no longer matches
 fn update_log_while_rebasing(options: &Options, log: &mut Log) -> Result<(), Box<dyn Error>> {
@@ -778,6 +1857,11 @@ fn update_log_while_rebasing(options: &Options, log: &mut Log) -> Result<(), Box
             continue;
         }
 
+        // Squashed backports no longer have a commit of their own
+        if log.get_annotation(&commits[i].0, "squash-into").is_some() {
+            continue;
+        }
+
         // Gather info about commit in log
         let commit = Git::show(&commits[i].0, &git_dir)?;
         let hash_log = commit.hash;
@@ -828,6 +1912,14 @@ pub fn cmd_update(options: &Options, log: &mut Log) -> Result<(), Box<dyn Error>
             continue;
         }
 
+        // Squashed backports are now part of the commit they were squashed into
+        if log.get_annotation(&commits[i].0, "squash-into").is_some() {
+            if commits[i].1.len() == 40 {
+                log.commit_update(&commits[i].0, "squashed")?;
+            }
+            continue;
+        }
+
         // Gather info about commit in log
         let commit = Git::show(&commits[i].0, &git_dir)?;
         let hash_log = commit.hash;
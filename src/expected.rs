@@ -0,0 +1,128 @@
+use std::fs;
+use std::path::Path;
+use std::error::Error;
+use colored::Colorize;
+use crate::Options;
+use crate::git::Git;
+use crate::patch::{Patch, PatchFile};
+
+/* Differences between the branch and range-stop that are known to be intentional. They are kept
+ * in b2tf.expected next to b2tf.log, one per line with the reason at the end
+ *
+ * commit <hash> <reason>           - changes of an upstream commit that is not backported
+ * path <path> <reason>             - all changes to files in a path
+ * hunk <fingerprint> <reason>      - a single hunk as fingerprinted by diff and diffdiff
+ */
+pub struct Expected {
+    pub commits: Vec<(String, String)>,
+    pub paths: Vec<(String, String)>,
+    pub hunks: Vec<(String, String)>,
+}
+
+impl Expected {
+    pub fn new() -> Expected {
+        Expected {
+            commits: vec![],
+            paths: vec![],
+            hunks: vec![],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commits.is_empty() && self.paths.is_empty() && self.hunks.is_empty()
+    }
+
+    pub fn load(options: &Options) -> Result<Expected, Box<dyn Error>> {
+        let work_dir = options.work_dir.clone().unwrap();
+        let path = Path::new(&work_dir).join("b2tf.expected");
+        let mut expected = Expected::new();
+
+        if !path.is_file() {
+            return Ok(expected);
+        }
+
+        for (i, line) in fs::read_to_string(&path)?.split("\n").enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("#") {
+                continue;
+            }
+
+            let mut words = line.splitn(3, " ");
+            let kind = words.next().unwrap_or_default();
+            let value = words.next().unwrap_or_default().to_string();
+            let reason = words.next().unwrap_or_default().trim().to_string();
+
+            if value.is_empty() || reason.is_empty() {
+                return Err(format!("b2tf.expected:{}: expected <kind> <value> <reason>", i + 1).red().into());
+            }
+
+            match kind {
+                "commit" => expected.commits.push((value, reason)),
+                "path" => expected.paths.push((value, reason)),
+                "hunk" => expected.hunks.push((value, reason)),
+                _ => return Err(format!("b2tf.expected:{}: unknown kind {}", i + 1, kind).red().into()),
+            }
+        }
+
+        Ok(expected)
+    }
+
+    fn path_matches(&self, file: &PatchFile) -> bool {
+        let source = PatchFile::path(&file.source_file);
+        let target = PatchFile::path(&file.target_file);
+
+        self.paths.iter().any(|(path, _)| {
+            let path = path.trim_end_matches("/");
+            [source, target].iter().any(|p| *p == path || p.starts_with(&format!("{}/", path)))
+        })
+    }
+
+    // Remove the expected differences from patch. Returns the number of hunks removed
+    pub fn apply(&self, options: &Options, patch: &mut Patch) -> Result<usize, Box<dyn Error>> {
+        let git_dir = options.git_dir.clone().unwrap();
+        let count = |p: &Patch| p.files.iter().map(|f| f.hunks.len().max(1)).sum::<usize>();
+        let before = count(patch);
+
+        for (hash, _) in &self.commits {
+            let diff = Git::diff_of(hash, &git_dir)?;
+            let mut commit = Patch::new();
+            commit.parse(&diff)?;
+            patch.subtract(commit, true);
+        }
+
+        patch.files.retain(|f| !self.path_matches(f));
+
+        for file in patch.files.iter_mut() {
+            file.hunks.retain(|h| !self.hunks.iter().any(|(fp, _)| *fp == h.fingerprint()));
+        }
+        patch.files.retain(|f| !f.hunks.is_empty() || f.has_metadata());
+
+        Ok(before - count(patch))
+    }
+}
+
+// Returns the diff between the branch and range-stop without the expected differences,
+// and the number of hunks that were hidden
+pub fn remaining_diff(options: &Options) -> Result<(Patch, usize), Box<dyn Error>> {
+    let git_dir = options.git_dir.clone().unwrap();
+    let branch = options.branch.clone().unwrap();
+    let range_stop = options.range_stop.clone().unwrap();
+    let paths = options.paths.clone().unwrap();
+
+    let diff = Git::cmd(format!("diff --full-index {branch} {range_stop} -- {paths}"), &git_dir)?;
+    let mut patch = Patch::new();
+    patch.parse(&diff)?;
+
+    let hidden = Expected::load(options)?.apply(options, &mut patch)?;
+
+    Ok((patch, hidden))
+}
+
+// Returns a note telling how many expected differences were hidden, if any
+pub fn hidden_note(hidden: usize) -> Option<String> {
+    if hidden == 0 {
+        return None;
+    }
+
+    Some(format!("{} expected differences hidden (see b2tf.expected)", hidden).bright_blue().to_string())
+}
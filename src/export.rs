@@ -0,0 +1,162 @@
+use std::fs;
+use std::path;
+use std::error::Error;
+use colored::Colorize;
+use mktemp::Temp;
+use crate::Options;
+use crate::Log;
+use crate::git::Git;
+use crate::commands::is_deviation;
+use crate::message;
+use crate::message::{Message, MessageStyle};
+
+// Split a format-patch file into the mail headers, the commit message body and the rest
+fn split_patch(contents: &str) -> Option<(&str, &str, &str)> {
+    let (headers, rest) = contents.split_once("\n\n")?;
+    let end = rest.find("\n---\n").map(|i| i + 1).unwrap_or(0);
+
+    Some((headers, &rest[..end], &rest[end..]))
+}
+
+// Add the reference to the upstream commit to a format-patch file. Exports always reference
+// upstream, as a cherry pick unless the stable style is used
+fn add_reference(log: &Log, style: &MessageStyle, file: &str) -> Result<bool, Box<dyn Error>> {
+    let contents = fs::read_to_string(file)?;
+    let cols: Vec<&str> = contents.split(" ").take(2).collect();
+    if cols.len() != 2 || cols[0] != "From" || cols[1].len() != 40 {
+        return Err(format!("Invalid patch file: {}", file).red().into());
+    }
+
+    // Downstream commits on the branch have nothing to reference
+    let upstream = match log.get_upstream(cols[1]) {
+        Ok(upstream) => upstream,
+        Err(_) => return Ok(false),
+    };
+
+    let (headers, body, rest) = match split_patch(&contents) {
+        Some(parts) => parts,
+        None => return Err(format!("Invalid patch file: {}", file).red().into()),
+    };
+
+    let mut msg = Message {
+        subject: String::new(),
+        body: body.trim().to_string(),
+    };
+
+    let style = match style {
+        MessageStyle::Stable => MessageStyle::Stable,
+        _ => MessageStyle::CherryPick,
+    };
+    message::add_reference(&style, &upstream, &mut msg);
+
+    fs::write(file, format!("{}\n\n{}\n{}", headers, msg.body, rest))?;
+
+    Ok(true)
+}
+
+// Describe the backport for the cover letter using the state of the log
+fn cover_letter_blurb(options: &Options, log: &Log) -> Result<String, Box<dyn Error>> {
+    let range_start = options.range_start.clone().unwrap();
+    let range_stop = options.range_stop.clone().unwrap();
+    let commits = log.get_all()?;
+
+    let count = |f: &dyn Fn(&str) -> bool| commits.iter().filter(|c| f(&c.1)).count();
+    let backported = count(&|s| s.len() == 40);
+    let skipped = count(&|s| s == "skip");
+    let empty = count(&|s| s == "empty");
+    let duplicates = count(&|s| s.starts_with("duplicate") || s.starts_with("cherry pick"));
+    let squashed = count(&|s| s == "squashed");
+    let pending = count(&|s| s.is_empty());
+
+    let mut blurb = format!("This series backports {} of the {} upstream commits in {}..{}.\n",
+                            backported, commits.len(), range_start, range_stop);
+
+    let mut stats = vec![];
+    for (num, what) in [(skipped, "skipped"), (empty, "empty"), (duplicates, "already present"),
+                        (squashed, "squashed"), (pending, "not applied yet")] {
+        if num > 0 {
+            stats.push(format!("{} {}", num, what));
+        }
+    }
+    if !stats.is_empty() {
+        blurb.push_str(&format!("Other commits were: {}.\n", stats.join(", ")));
+    }
+
+    let conflicts: Vec<&(String, String)> = commits.iter()
+        .filter(|c| c.1.len() == 40 && log.get_annotation(&c.0, "conflicts").is_some())
+        .collect();
+    if !conflicts.is_empty() {
+        blurb.push_str("\nCommits that needed manual resolution:\n");
+        for (upstream, _) in conflicts {
+            let paths = log.get_annotation(upstream, "conflicts").unwrap();
+            blurb.push_str(&format!("  {} ({})\n", &upstream[..12], paths));
+        }
+    }
+
+    let deviations: Vec<&(String, String)> = commits.iter()
+        .filter(|c| log.get_annotation(&c.0, "compare").is_some_and(|r| is_deviation(&r)))
+        .collect();
+    if !deviations.is_empty() {
+        blurb.push_str("\nBackports that deviate from upstream:\n");
+        for (upstream, _) in deviations {
+            let res = log.get_annotation(upstream, "compare").unwrap();
+            blurb.push_str(&format!("  {} ({})\n", &upstream[..12], res));
+        }
+    }
+
+    Ok(blurb.trim_end().to_string())
+}
+
+pub fn cmd_export(options: &Options, log: &Log) -> Result<(), Box<dyn Error>> {
+    export_series(options, log, MessageStyle::from_options(options))
+}
+
+// Export branch-point..branch as a patch series with a cover letter, into a directory or an mbox
+pub fn export_series(options: &Options, log: &Log, style: MessageStyle) -> Result<(), Box<dyn Error>> {
+    let git_dir = options.git_dir.clone().unwrap();
+    let work_dir = options.work_dir.clone().unwrap();
+    let branch = options.branch.clone().unwrap();
+    let branch_point = options.branch_point.clone().unwrap();
+
+    // An mbox is assembled from the patches in a temporary directory
+    let temp_dir = Temp::new_dir()?;
+    let out_dir = match (&options.mbox, &options.output) {
+        (Some(_), _) => temp_dir.to_path_buf(),
+        (None, Some(output)) => path::absolute(output)?,
+        (None, None) => path::absolute(path::Path::new(&work_dir).join("patches"))?,
+    };
+    let out_dir = out_dir.display().to_string();
+
+    let files = Git::cmd(format!("format-patch --cover-letter --thread -o {} {}..{}",
+                                 Git::quote(&out_dir), branch_point, branch), &git_dir)?;
+    let files: Vec<&str> = files.split_whitespace().collect();
+    if files.len() < 2 {
+        return Err(format!("No commits between {} and {}", branch_point, branch).red().into());
+    }
+
+    let subject = format!("Backport of {}..{}", options.range_start.clone().unwrap(), options.range_stop.clone().unwrap());
+    let cover = fs::read_to_string(files[0])?
+        .replace("*** SUBJECT HERE ***", &subject)
+        .replace("*** BLURB HERE ***", &cover_letter_blurb(options, log)?);
+    fs::write(files[0], cover)?;
+
+    for file in files.iter().skip(1) {
+        if !add_reference(log, &style, file)? {
+            println!("{} {}", "No upstream commit for:".yellow(), file);
+        }
+    }
+
+    match &options.mbox {
+        Some(mbox) => {
+            let mut contents = String::new();
+            for file in &files {
+                contents.push_str(&fs::read_to_string(file)?);
+            }
+            fs::write(mbox, contents)?;
+            println!("Exported {} patches into {}", files.len() - 1, mbox);
+        },
+        None => println!("Exported {} patches into {}", files.len() - 1, out_dir),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,221 @@
+use std::error::Error;
+use clap::{Arg, ArgMatches, Command};
+use colored::Colorize;
+use crate::Options;
+use crate::Log;
+use crate::git::Git;
+use crate::export;
+use crate::suse::Suse;
+use crate::rhel::Rhel;
+use crate::ubuntu::Ubuntu;
+use crate::stable::Stable;
+
+/* The conventions of a downstream tree. The flavor is picked with the flavor config key
+ *
+ * suse     - SUSE kernel-source, tags are added to the exported patches
+ * rhel     - Red Hat and CentOS Stream, tags are added to the backported commits
+ * ubuntu   - Ubuntu and Debian derived kernels, tags are added to the backported commits
+ * stable   - Linux stable releases, backports reference upstream in their first line
+ *
+ * Flavors bring their own config keys and subcommand, so b2tf itself knows nothing about them.
+ * Their config keys are kept in Options::flavor_config, and can also be given as options of
+ * apply, export, validate, apply-downstream and the subcommand of the flavor.
+ */
+pub trait Flavor {
+    fn name(&self) -> &str;
+
+    // Returns the config keys of the flavor and their help
+    fn config_keys(&self) -> Vec<(&'static str, &'static str)> {
+        vec![]
+    }
+
+    // Returns the subcommand of the flavor, named after it
+    fn command(&self) -> Option<Command> {
+        None
+    }
+
+    // Run the subcommand of the flavor
+    fn run(&self, _options: &mut Options, _log: &mut Log, _matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    // Add the downstream tags to the message of a backport of upstream
+    fn tag_message(&self, options: &Options, log: &Log, upstream: &str, text: &str) -> Result<String, Box<dyn Error>>;
+
+    // Check that a commit on the branch follows the conventions. Upstream is None for commits
+    // that are not in the log. Returns the problems found
+    fn validate(&self, options: &Options, log: &Log, upstream: Option<&str>, text: &str) -> Vec<String>;
+
+    // Returns (downstream hash, upstream hash) of upstream commits that are already in the
+    // downstream tree. Apply marks them like cherry picks
+    fn picks(&self, _options: &Options) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        Ok(vec![])
+    }
+
+    // Export the backport in the format of the downstream tree
+    fn export(&self, options: &Options, log: &Log) -> Result<(), Box<dyn Error>> {
+        export::cmd_export(options, log)
+    }
+
+    // Apply the exported backport to the downstream tree
+    fn apply_downstream(&self, _options: &Options, _log: &Log) -> Result<(), Box<dyn Error>> {
+        Err(format!("The {} flavor has no downstream tree to apply to", self.name()).red().into())
+    }
+}
+
+pub fn all() -> Vec<Box<dyn Flavor>> {
+    vec![Box::new(Suse {}), Box::new(Rhel {}), Box::new(Ubuntu {}), Box::new(Stable {})]
+}
+
+pub fn from_options(options: &Options) -> Result<Option<Box<dyn Flavor>>, Box<dyn Error>> {
+    let name = match &options.flavor {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+
+    match all().into_iter().find(|f| f.name() == name) {
+        Some(flavor) => Ok(Some(flavor)),
+        None => Err(format!("Unknown flavor: {}", name).red().into()),
+    }
+}
+
+// Subcommands that act on the configured flavor and take its config keys as options
+const FLAVORED_COMMANDS: [&str; 4] = ["apply", "export", "validate", "apply-downstream"];
+
+// Returns true if a flavor claims key as one of its config keys
+pub fn is_config_key(key: &str) -> bool {
+    all().iter().any(|f| f.config_keys().iter().any(|k| k.0 == key))
+}
+
+// Add the subcommands of the flavors and their config keys as options. The keys of all flavors
+// are options of the subcommands that act on the configured flavor, and the keys of a flavor are
+// also options of its own subcommand
+pub fn register(mut command: Command) -> Command {
+    let mut keys: Vec<(&'static str, &'static str)> = vec![];
+
+    for flavor in all() {
+        for key in flavor.config_keys() {
+            if !keys.iter().any(|k| k.0 == key.0) {
+                keys.push(key);
+            }
+        }
+
+        let mut subcommand = match flavor.command() {
+            Some(subcommand) => subcommand,
+            None => continue,
+        };
+
+        for (key, help) in flavor.config_keys() {
+            subcommand = subcommand.arg(Arg::new(key).long(key).help(help).global(true));
+        }
+        command = command.subcommand(subcommand);
+    }
+
+    for name in FLAVORED_COMMANDS {
+        command = command.mut_subcommand(name, |mut subcommand| {
+            for (key, help) in &keys {
+                subcommand = subcommand.arg(Arg::new(*key).long(*key).help(*help));
+            }
+            subcommand
+        });
+    }
+
+    command
+}
+
+// Read the config keys of the flavors given on the command line. Options given to a nested
+// subcommand are only found in its matches
+pub fn parse_matches(options: &mut Options, matches: &ArgMatches) {
+    let keys: Vec<&'static str> = all().iter().flat_map(|f| f.config_keys()).map(|k| k.0).collect();
+    let mut m = matches;
+
+    while let Some((_, sub_m)) = m.subcommand() {
+        m = sub_m;
+        for key in &keys {
+            // Fails for keys that are not options of this subcommand
+            if let Ok(Some(value)) = m.try_get_one::<String>(key) {
+                options.flavor_config.push((key.to_string(), value.clone()));
+            }
+        }
+    }
+}
+
+// Run the subcommand of a flavor. Returns false if no flavor subcommand was given
+pub fn dispatch(options: &mut Options, log: &mut Log, matches: &ArgMatches) -> Result<bool, Box<dyn Error>> {
+    let (name, flavor_matches) = match matches.subcommand() {
+        Some(subcommand) => subcommand,
+        None => return Ok(false),
+    };
+
+    for flavor in all() {
+        if flavor.name() != name || flavor.command().is_none() {
+            continue;
+        }
+
+        match flavor_matches.subcommand() {
+            Some(_) => flavor.run(options, log, flavor_matches)?,
+            None => { let _ = flavor.command().unwrap().print_help(); },
+        }
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+// Returns the first release containing hash. Commits imported from a mailing list are Submitted,
+// with a link to the posting if the Message-Id is known
+pub fn mainline_tag(hash: &str, log: &Log, git_dir: &String) -> Result<String, Box<dyn Error>> {
+    if let Some(submitted) = log.get_annotation(hash, "submitted") {
+        let msg_id = submitted.trim().trim_start_matches("<").trim_end_matches(">");
+        if msg_id.contains("@") {
+            return Ok(format!("Submitted, https://lore.kernel.org/r/{}", msg_id));
+        }
+        return Ok(format!("Submitted, {}", msg_id));
+    }
+
+    let line = Git::cmd(format!("describe --contains --match 'v*' {}", hash), git_dir)?;
+    let tag: Vec<&str> = line.split("~").collect();
+    let mainline = tag[0].trim().to_string();
+
+    Ok(mainline)
+}
+
+// Check that the commits on the branch follow the conventions of the flavor
+pub fn cmd_validate(options: &Options, log: &Log) -> Result<(), Box<dyn Error>> {
+    let git_dir = options.git_dir.clone().unwrap();
+    let branch = options.branch.clone().unwrap();
+    let branch_point = options.branch_point.clone().unwrap();
+
+    let flavor = match from_options(options)? {
+        Some(flavor) => flavor,
+        None => return Err("No flavor configured. Set flavor in b2tf.log".red().into()),
+    };
+
+    let hashes = Git::cmd(format!("rev-list --reverse {}..{}", branch_point, branch), &git_dir)?;
+    let mut failed = 0;
+
+    for hash in hashes.split_whitespace() {
+        let commit = Git::show(hash, &git_dir)?;
+        let text = Git::cmd(format!("log -1 --format=%B {}", hash), &git_dir)?;
+        let upstream = log.get_upstream(hash).ok();
+        let problems = flavor.validate(options, log, upstream.as_deref(), &text);
+
+        if problems.is_empty() {
+            continue;
+        }
+
+        failed += 1;
+        println!("{} {}", hash[..12].yellow(), commit.subject);
+        for problem in problems {
+            println!("  {}", problem);
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!("{} commits don't follow the {} conventions", failed, flavor.name()).red().into());
+    }
+
+    println!("{}", format!("All commits follow the {} conventions", flavor.name()).green());
+
+    Ok(())
+}
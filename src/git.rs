@@ -26,6 +26,11 @@ pub enum GitSessionState {
 }
 
 impl Git {
+    // Quote a string so it can be passed as a single argument in a query
+    pub fn quote(arg: &str) -> String {
+        format!("'{}'", arg.replace("'", "'\\''"))
+    }
+
     // Execute query in git repository located at dir
     pub fn cmd(query: String, dir: &String) -> Result<String, Box<dyn Error>> {
 
@@ -84,6 +89,11 @@ impl Git {
         Ok(commit)
     }
 
+    // Returns the patch of a commit without its message
+    pub fn diff_of(hash: &str, dir: &String) -> Result<String, Box<dyn Error>> {
+        Git::cmd(format!("show --format= --no-color --no-ext-diff --full-index -n1 {}", hash), dir)
+    }
+
     pub fn get_last_commit(dir: &String) -> Result<String, Box<dyn Error>> {
         let res = Git::cmd("log --format='%H' -n 1".to_string(), dir);
 
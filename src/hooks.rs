@@ -0,0 +1,122 @@
+use std::process::Command;
+use std::error::Error;
+use std::path::Path;
+use std::path;
+use colored::Colorize;
+use crate::Options;
+use crate::Log;
+use crate::git::*;
+
+/* User scripts run at points of the backport lifecycle. A hook is an executable
+ * named after the hook in the b2tf-hooks/ directory next to b2tf.log
+ *
+ * PreApply     - before an upstream commit is cherry picked
+ * PostApply    - after an upstream commit was backported
+ * OnConflict   - when a cherry pick needs manual resolution
+ * OnSkip       - before an upstream commit is skipped
+ * PostPopulate - after the log was populated with upstream commits
+ *
+ * A non-zero exit code vetoes the action. PostApply runs once the backport is
+ * already recorded in the log, so it can only stop the run, not undo the pick.
+ */
+#[derive(PartialEq, Debug)]
+pub enum Hook {
+    PreApply,
+    PostApply,
+    OnConflict,
+    OnSkip,
+    PostPopulate,
+}
+
+impl Hook {
+    pub fn name(&self) -> &str {
+        match self {
+            Hook::PreApply => "pre-apply",
+            Hook::PostApply => "post-apply",
+            Hook::OnConflict => "on-conflict",
+            Hook::OnSkip => "on-skip",
+            Hook::PostPopulate => "post-populate",
+        }
+    }
+}
+
+fn hook_path(options: &Options, hook: &Hook) -> Result<Option<String>, Box<dyn Error>> {
+    let work_dir = options.work_dir.clone().unwrap();
+    let path = path::absolute(Path::new(&work_dir).join("b2tf-hooks").join(hook.name()))?;
+
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    Ok(Some(path.display().to_string()))
+}
+
+// Run hook for the upstream commit (which may be empty). Returns false if the hook vetoed the action.
+// Hooks can edit b2tf.log, so the log is reloaded after the hook has run
+pub fn run(options: &Options, log: &mut Log, hook: Hook, upstream: &str) -> Result<bool, Box<dyn Error>> {
+    let path = match hook_path(options, &hook)? {
+        Some(path) => path,
+        None => return Ok(true),
+    };
+
+    let git_dir = options.git_dir.clone().unwrap();
+    let work_dir = options.work_dir.clone().unwrap();
+    let session = Git::get_session(&git_dir)?;
+
+    let state = match session.state {
+        GitSessionState::None => "none",
+        GitSessionState::Rebase => "rebase",
+        GitSessionState::Cherrypick => "cherry-pick",
+    };
+
+    let commits = log.get_all()?;
+    let (index, status) = match commits.iter().position(|c| c.0 == upstream) {
+        Some(pos) => ((pos + 1).to_string(), commits[pos].1.clone()),
+        None => (String::new(), String::new()),
+    };
+
+    let backport = if status.len() == 40 { status.clone() } else { String::new() };
+    let conflicts: Vec<String> = session.unmerged_paths.iter().map(|p| p.1.clone()).collect();
+    let log_path = path::absolute(Path::new(&work_dir).join(&log.filename))?;
+
+    println!("{} {}", "Running hook:".bright_blue(), hook.name());
+
+    // Make sure the hook sees our latest changes
+    log.save()?;
+
+    let res = Command::new(&path)
+        .current_dir(&work_dir)
+        .env("B2TF_HOOK", hook.name())
+        .env("B2TF_UPSTREAM", upstream)
+        .env("B2TF_BACKPORT", backport)
+        .env("B2TF_STATUS", status)
+        .env("B2TF_INDEX", index)
+        .env("B2TF_COUNT", commits.len().to_string())
+        .env("B2TF_CONFLICTS", conflicts.join(" "))
+        .env("B2TF_STATE", state)
+        .env("B2TF_LOG", log_path.display().to_string())
+        .env("B2TF_GIT_DIR", &git_dir)
+        .env("B2TF_BRANCH", options.branch.clone().unwrap_or_default())
+        .status();
+
+    let status = match res {
+        Ok(status) => status,
+        Err(e) => return Err(format!("Failed to run hook {}: {}", path, e).red().into()),
+    };
+
+    log.load(&work_dir)?;
+
+    Ok(status.success())
+}
+
+// Run hook and turn a veto into an error
+pub fn check(options: &Options, log: &mut Log, hook: Hook, upstream: &str) -> Result<(), Box<dyn Error>> {
+    let name = hook.name().to_string();
+
+    if !run(options, log, hook, upstream)? {
+        let what = if upstream.is_empty() { String::new() } else { format!(" for {}", upstream) };
+        return Err(format!("Hook {} vetoed the action{}", name, what).red().into());
+    }
+
+    Ok(())
+}
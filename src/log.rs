@@ -3,8 +3,12 @@ use std::fs;
 use std::error::Error;
 use crate::cli::*;
 use crate::Options;
+use crate::flavor;
 use colored::Colorize;
 
+// Annotations that describe a backport attempt and are removed when the entry is reset
+const RESET_ANNOTATIONS: [&str; 4] = ["conflicts", "verify", "partial", "compare"];
+
 #[derive(Debug, Clone)]
 pub struct Log {
     pub filename: String,
@@ -64,9 +68,6 @@ impl Log {
             } else if name == "range-stop" {
                 options.range_stop = Some(value.to_string());
 
-            } else if name == "range-guard" {
-                options.range_guard = Some(value.to_string());
-
             } else if name == "branch" {
                 options.branch = Some(value.to_string());
 
@@ -82,18 +83,62 @@ impl Log {
             } else if name == "paths" {
                 options.paths = Some(value.to_string());
 
-            } else if name == "signature" {
-                options.signature = Some(value.to_string());
+            } else if name == "message-style" {
+                options.message_style = Some(value.to_string());
+
+            } else if name == "trailer" {
+                options.trailers.push(value.to_string());
+
+            } else if name == "conflict-note" {
+                options.conflict_note = Some(value.to_string());
+
+            } else if name == "signoff" {
+                options.signoff = value == "yes" || value == "true";
+
+            } else if name == "sign-commits" {
+                options.sign_commits = Some(value.to_string());
+
+            } else if name == "sign-format" {
+                options.sign_format = Some(value.to_string());
+
+            } else if name == "committer-name" {
+                options.committer_name = Some(value.to_string());
+
+            } else if name == "committer-email" {
+                options.committer_email = Some(value.to_string());
+
+            } else if name == "preserve-committer" {
+                options.preserve_committer = value == "yes" || value == "true";
+
+            } else if name == "verify" {
+                options.verify = Some(value.to_string());
+
+            } else if name == "verify-interval" {
+                options.verify_interval = Some(value.to_string());
+
+            } else if name == "verify-bisect" {
+                options.verify_bisect = value == "yes" || value == "true";
+
+            } else if name == "partial-threshold" {
+                options.partial_threshold = Some(value.to_string());
+
+            } else if name == "color" {
+                options.color = Some(value.to_string());
 
-            } else if name == "references" {
-                options.references = Some(value.to_string());
+            } else if name == "layout" {
+                options.layout = Some(value.to_string());
 
-            } else if name == "suse-kernel-source" {
-                options.kernel_source = Some(value.to_string());
+            } else if name == "pager" {
+                options.pager = Some(value.to_string());
 
-            } else if name == "guard-prefix" {
-                options.guard_prefix = Some(value.to_string());
+            } else if name == "flavor" {
+                options.flavor = Some(value.to_string());
 
+            } else if flavor::is_config_key(name) {
+                options.flavor_config.push((name.to_string(), value.to_string()));
+
+            } else {
+                println!("{} {}", "Unknown config key in b2tf.log:".yellow(), name);
             }
         };
 
@@ -240,6 +285,215 @@ impl Log {
         Ok(last_applied)
     }
 
+    // Annotations are "# key: value" lines directly following the entry of an upstream hash
+    fn parse_annotation(line: &str) -> Option<(&str, &str)> {
+        let line = line.trim();
+        if !line.starts_with("# ") {
+            return None;
+        }
+
+        let (key, value) = line[2..].split_once(": ")?;
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_lowercase() || c == '-') {
+            return None;
+        }
+
+        Some((key, value.trim()))
+    }
+
+    // Returns the value of annotation key for the entry of upstream hash
+    pub fn get_annotation(&self, hash: &str, key: &str) -> Option<String> {
+        let lines: Vec<&str> = self.commits.split("\n").collect();
+        let mut in_entry = false;
+
+        for line in lines.iter() {
+            let cols: Vec<&str> = line.trim().split(" ").collect();
+
+            if cols[0].len() == 40 {
+                in_entry = cols[0] == hash;
+                continue;
+            }
+
+            if !in_entry {
+                continue;
+            }
+
+            match Log::parse_annotation(line) {
+                Some((k, v)) => {
+                    if k == key {
+                        return Some(v.to_string());
+                    }
+                },
+                None => in_entry = false,
+            }
+        }
+
+        None
+    }
+
+    // Set annotation key to value for the entry of upstream hash. An empty value removes it
+    pub fn set_annotation(&mut self, hash: &str, key: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        self.update_annotation(hash, key, value)?;
+        self.save()
+    }
+
+    // Set annotation key for many entries as (upstream hash, value) and save the log once
+    pub fn set_annotations(&mut self, key: &str, values: &[(String, String)]) -> Result<(), Box<dyn Error>> {
+        for (hash, value) in values {
+            self.update_annotation(hash, key, value)?;
+        }
+        self.save()
+    }
+
+    fn update_annotation(&mut self, hash: &str, key: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        let lines: Vec<&str> = self.commits.split("\n").collect();
+        let mut commits = String::from("");
+        let mut in_entry = false;
+        let mut found = false;
+
+        for line in lines.iter() {
+            let cols: Vec<&str> = line.trim().split(" ").collect();
+
+            if in_entry {
+                match Log::parse_annotation(line) {
+                    Some((k, _v)) => {
+                        if k == key {
+                            continue;
+                        }
+                    },
+                    None => in_entry = false,
+                }
+            }
+
+            commits.push_str(line);
+            commits.push('\n');
+
+            if cols[0].len() == 40 && cols[0] == hash {
+                in_entry = true;
+                found = true;
+
+                if !value.is_empty() {
+                    commits.push_str(format!("# {}: {}\n", key, value).as_str());
+                }
+            }
+        }
+
+        if !found {
+            return Err(format!("Hash {} not found in log", hash).red().into());
+        }
+
+        // Remove the last newline in the log
+        commits.pop();
+
+        self.commits = commits;
+        Ok(())
+    }
+
+    // Returns the log as one block of text per entry as a vector of (upstream hash, block)
+    // A block holds the comments preceding the entry, the entry itself and its annotations
+    pub fn get_blocks(&self) -> Vec<(String, String)> {
+        let lines: Vec<&str> = self.commits.split("\n").collect();
+        let mut blocks: Vec<(String, String)> = vec![];
+        let mut current: Vec<&str> = vec![];
+        let mut hash = String::new();
+
+        for line in lines.iter() {
+            if !hash.is_empty() {
+                if Log::parse_annotation(line).is_some() {
+                    current.push(line);
+                    continue;
+                }
+                blocks.push((hash.clone(), Log::join_block(&current)));
+                current.clear();
+                hash.clear();
+            }
+
+            let cols: Vec<&str> = line.trim().split(" ").collect();
+            if cols[0].len() == 40 {
+                hash = cols[0].to_string();
+            }
+            current.push(line);
+        }
+
+        if !hash.is_empty() {
+            blocks.push((hash, Log::join_block(&current)));
+        } else if !blocks.is_empty() {
+            // Keep any trailing comments together with the last entry
+            let last = blocks.len() - 1;
+            let tail = Log::join_block(&current);
+            if !tail.is_empty() {
+                blocks[last].1.push_str(format!("\n\n{}", tail).as_str());
+            }
+        }
+
+        blocks
+    }
+
+    // Join the lines of a block without any leading or trailing empty lines
+    fn join_block(lines: &[&str]) -> String {
+        let start = lines.iter().position(|l| !l.trim().is_empty());
+        let end = lines.iter().rposition(|l| !l.trim().is_empty());
+
+        match (start, end) {
+            (Some(start), Some(end)) => lines[start..=end].join("\n"),
+            _ => String::new(),
+        }
+    }
+
+    // Replace all entries in the log with blocks as returned by get_blocks()
+    pub fn set_blocks(&mut self, blocks: &[String]) -> Result<(), Box<dyn Error>> {
+        // Like the other updates the log doesn't end in a newline
+        self.commits = blocks.join("\n\n");
+        self.save()
+    }
+
+    // Clear the backport information of all entries starting at upstream hash (or all entries if None)
+    pub fn reset_entries(&mut self, from: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let lines: Vec<&str> = self.commits.split("\n").collect();
+        let mut commits = String::from("");
+        let mut resetting = from.is_none();
+        let mut in_entry = false;
+
+        for line in lines.iter() {
+            let cols: Vec<&str> = line.trim().split(" ").collect();
+
+            if cols[0].len() == 40 {
+                if from == Some(cols[0]) {
+                    resetting = true;
+                }
+                in_entry = resetting;
+
+                if resetting {
+                    commits.push_str(cols[0]);
+                    commits.push('\n');
+                    continue;
+                }
+            } else if in_entry {
+                match Log::parse_annotation(line) {
+                    Some((key, _value)) => {
+                        if RESET_ANNOTATIONS.contains(&key) {
+                            continue;
+                        }
+                    },
+                    None => in_entry = false,
+                }
+            }
+
+            commits.push_str(line);
+            commits.push('\n');
+        }
+
+        if !resetting {
+            return Err(format!("Hash {} not found in log", from.unwrap()).red().into());
+        }
+
+        // Remove the last newline in the log
+        commits.pop();
+
+        self.commits = commits;
+        self.save()?;
+        Ok(())
+    }
+
     pub fn get_upstream(&self, hash: &str) -> Result<String, Box<dyn Error>> {
         let commits = self.get_all()?;
 
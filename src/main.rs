@@ -5,11 +5,21 @@ mod git;
 mod util;
 mod suse;
 mod patch;
+mod message;
+mod verify;
+mod hooks;
+mod expected;
+mod render;
+mod series;
+mod export;
+mod flavor;
+mod rhel;
+mod ubuntu;
+mod stable;
 use crate::log::*;
 use crate::cli::*;
 use crate::commands::*;
 use crate::util::*;
-use crate::suse::cmd_suse;
 use crate::git::*;
 use clap::ArgMatches;
 use std::env;
@@ -21,21 +31,39 @@ use colored::Colorize;
 pub struct Options {
     pub range_start:    Option<String>,
     pub range_stop:     Option<String>,
-    pub range_guard:    Option<String>,
-    pub guard_prefix:   Option<String>,
     pub branch:         Option<String>,
     pub branch_point:   Option<String>,
     pub work_dir:       Option<String>,
     pub git_dir:        Option<String>,
     pub paths:          Option<String>,
-    pub signature:      Option<String>,
-    pub references:     Option<String>,
-    pub kernel_source:  Option<String>,
     pub hash:           Option<String>,
     pub after:          Option<String>,
+    pub mbox:           Option<String>,
+    pub base:           Option<String>,
+    pub output:         Option<String>,
     pub skip:           Option<String>,
     pub patch1:         Option<String>,
     pub patch2:         Option<String>,
+    pub message_style:  Option<String>,
+    pub trailers:       Vec<String>,
+    pub conflict_note:  Option<String>,
+    pub signoff:        bool,
+    pub sign_commits:   Option<String>,
+    pub sign_format:    Option<String>,
+    pub committer_name: Option<String>,
+    pub committer_email: Option<String>,
+    pub preserve_committer: bool,
+    pub verify:         Option<String>,
+    pub verify_interval: Option<String>,
+    pub verify_bisect:  bool,
+    pub partial_threshold: Option<String>,
+    pub keep_resolutions: bool,
+    pub show_all:       bool,
+    pub color:          Option<String>,
+    pub layout:         Option<String>,
+    pub pager:          Option<String>,
+    pub flavor:         Option<String>,
+    pub flavor_config:  Vec<(String, String)>,
 }
 
 impl Options {
@@ -43,21 +71,39 @@ impl Options {
         Options {
             range_start: None,
             range_stop:  None,
-            range_guard: None,
-            guard_prefix: None,
             branch: None,
             branch_point: None,
             work_dir: None,
             git_dir: None,
             paths: None,
-            signature: None,
-            references: None,
-            kernel_source: None,
             hash: None,
             after: None,
+            mbox: None,
+            base: None,
+            output: None,
             skip: None,
             patch1: None,
             patch2: None,
+            message_style: None,
+            trailers: vec![],
+            conflict_note: None,
+            signoff: false,
+            sign_commits: None,
+            sign_format: None,
+            committer_name: None,
+            committer_email: None,
+            preserve_committer: false,
+            verify: None,
+            verify_interval: None,
+            verify_bisect: false,
+            partial_threshold: None,
+            keep_resolutions: false,
+            show_all: false,
+            color: None,
+            layout: None,
+            pager: None,
+            flavor: None,
+            flavor_config: vec![],
         }
     }
 
@@ -69,6 +115,8 @@ impl Options {
         let work_dir = matches.get_one::<String>("work directory").cloned();
         let git_dir = matches.get_one::<String>("git directory").cloned();
         let paths = matches.get_one::<String>("paths").cloned();
+        let color = matches.get_one::<String>("color").cloned();
+        let layout = matches.get_one::<String>("layout").cloned();
 
         let prepend_matches = matches.subcommand_matches("prepend");
         if prepend_matches.is_some() {
@@ -91,31 +139,61 @@ impl Options {
             if after.is_some() { self.after = after }
         }
 
+        if let Some(import_matches) = matches.subcommand_matches("import") {
+            let mbox = import_matches.get_one::<String>("mbox file").cloned();
+            if mbox.is_some() { self.mbox = mbox }
+
+            let base = import_matches.get_one::<String>("base commit").cloned();
+            if base.is_some() { self.base = base }
+
+            let after = import_matches.get_one::<String>("insert after this hash").cloned();
+            if after.is_some() { self.after = after }
+        }
+
+        if let Some(export_matches) = matches.subcommand_matches("export") {
+            let output = export_matches.get_one::<String>("output directory").cloned();
+            if output.is_some() { self.output = output }
+
+            let mbox = export_matches.get_one::<String>("mbox file").cloned();
+            if mbox.is_some() { self.mbox = mbox }
+
+            let style = export_matches.get_one::<String>("reference style").cloned();
+            if style.is_some() { self.message_style = style }
+        }
+
+        if let Some(goto_matches) = matches.subcommand_matches("goto") {
+            let hash = goto_matches.get_one::<String>("commit to restart from").cloned();
+            if hash.is_some() { self.hash = hash }
+
+            self.keep_resolutions = goto_matches.get_flag("keep resolutions");
+        }
+
+        if let Some(range_diff_matches) = matches.subcommand_matches("range-diff") {
+            self.show_all = range_diff_matches.get_flag("show all");
+        }
+
         let diffdiff_matches = matches.subcommand_matches("diffdiff");
         if diffdiff_matches.is_some() {
             let skip = diffdiff_matches.unwrap().get_one::<String>("comma separated list of commits to skip").cloned();
             if skip.is_some() { self.skip = skip }
         }
 
-        let compare_matches = matches.subcommand_matches("compare");
-        if compare_matches.is_some() {
-            let patch1 = compare_matches.unwrap().get_one::<String>("patch 1").cloned();
-            let patch2 = compare_matches.unwrap().get_one::<String>("patch 2").cloned();
+        if let Some(compare_matches) = matches.subcommand_matches("compare") {
+            let patch1 = compare_matches.get_one::<String>("patch 1").cloned()
+                .or(compare_matches.get_one::<String>("first").cloned());
+            let patch2 = compare_matches.get_one::<String>("patch 2").cloned()
+                .or(compare_matches.get_one::<String>("second").cloned());
 
             if patch1.is_some() { self.patch1 = patch1 }
             if patch2.is_some() { self.patch2 = patch2 }
         }
 
-        let suse_matches = matches.subcommand_matches("suse");
-        if suse_matches.is_some() {
-            let apply_matches = suse_matches.unwrap().subcommand_matches("apply");
-            if apply_matches.is_some() {
-                let range_guard = apply_matches.unwrap().get_one::<String>("range guard").cloned();
-                if range_guard.is_some() { self.range_guard = range_guard }
+        if let Some(series_matches) = matches.subcommand_matches("compare-series") {
+            let series1 = series_matches.get_one::<String>("first series").cloned();
+            let series2 = series_matches.get_one::<String>("second series").cloned();
 
-                let guard_prefix = apply_matches.unwrap().get_one::<String>("guard prefix").cloned();
-                if guard_prefix.is_some() { self.guard_prefix = guard_prefix }
-            }
+            if series1.is_some() { self.patch1 = series1 }
+            if series2.is_some() { self.patch2 = series2 }
         }
 
         if range_start.is_some() { self.range_start = range_start }
@@ -125,6 +203,13 @@ impl Options {
         if work_dir.is_some() { self.work_dir = work_dir }
         if git_dir.is_some() { self.git_dir = git_dir }
         if paths.is_some() { self.paths = paths }
+        if color.is_some() { self.color = color }
+        if layout.is_some() { self.layout = layout }
+    }
+
+    // Returns the value of a config key of the flavor. Later values override earlier ones
+    pub fn flavor_value(&self, key: &str) -> Option<String> {
+        self.flavor_config.iter().rev().find(|c| c.0 == key).map(|c| c.1.clone())
     }
 
     pub fn parse(&mut self, matches :&ArgMatches, log :&Log) -> Result<(), Box<dyn Error>> {
@@ -135,6 +220,7 @@ impl Options {
 
         // Parse options from cli
         self.parse_matches(matches);
+        flavor::parse_matches(self, matches);
 
         Ok(())
     }
@@ -174,6 +260,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     };
 
     options.parse(&matches, &log)?;
+    render::setup_color(&options);
 
     if matches.get_flag("debug") {
         println!("Options: {:?}", options);
@@ -203,12 +290,9 @@ fn main() -> Result<(), Box<dyn Error>> {
     if options.branch_point.is_none() {
         options.branch_point = options.range_start.clone();
     }
-    if options.range_guard.is_none() {
-        options.range_guard = options.range_stop.clone();
-    }
-    if options.guard_prefix.is_none() {
-        options.guard_prefix = Some("b2tf".to_string());
-    }
+
+    // Fail early on an unknown flavor
+    flavor::from_options(&options)?;
 
     Git::set_branch(&options.branch.clone().unwrap(),
                     &options.branch_point.clone().unwrap(),
@@ -224,16 +308,24 @@ fn main() -> Result<(), Box<dyn Error>> {
         cmd_edit(&options, &mut log)?;
     } else if let Some(_matches) = matches.subcommand_matches("restart") {
         cmd_restart(&options, &mut log)?;
+    } else if let Some(_matches) = matches.subcommand_matches("goto") {
+        cmd_goto(&options, &mut log)?;
     } else if let Some(_matches) = matches.subcommand_matches("skip") {
         cmd_skip(&options, &mut log)?;
     } else if let Some(_matches) = matches.subcommand_matches("diff") {
         cmd_diff(&options)?;
     } else if let Some(_matches) = matches.subcommand_matches("diffdiff") {
-        cmd_diffdiff(&options)?;
+        cmd_diffdiff(&options, &log)?;
+    } else if let Some(_matches) = matches.subcommand_matches("deviations") {
+        cmd_deviations(&options, &mut log)?;
+    } else if let Some(_matches) = matches.subcommand_matches("range-diff") {
+        cmd_range_diff(&options, &log)?;
     } else if let Some(_matches) = matches.subcommand_matches("diffstat") {
         cmd_diffstat(&options)?;
     } else if let Some(_matches) = matches.subcommand_matches("rebase") {
         cmd_rebase(&options, &mut log)?;
+    } else if let Some(_matches) = matches.subcommand_matches("edit-list") {
+        cmd_edit_list(&options, &mut log)?;
     } else if let Some(_matches) = matches.subcommand_matches("update") {
         cmd_update(&options, &mut log)?;
     } else if let Some(_matches) = matches.subcommand_matches("prepend") {
@@ -242,12 +334,27 @@ fn main() -> Result<(), Box<dyn Error>> {
         cmd_append(&options, &mut log)?;
     } else if let Some(_matches) = matches.subcommand_matches("insert") {
         cmd_insert(&options, &mut log)?;
+    } else if let Some(_matches) = matches.subcommand_matches("import") {
+        cmd_import(&mut options, &mut log)?;
+    } else if let Some(_matches) = matches.subcommand_matches("export") {
+        match flavor::from_options(&options)? {
+            Some(flavor) => flavor.export(&options, &log)?,
+            None => export::cmd_export(&options, &log)?,
+        }
+    } else if let Some(_matches) = matches.subcommand_matches("apply-downstream") {
+        match flavor::from_options(&options)? {
+            Some(flavor) => flavor.apply_downstream(&options, &log)?,
+            None => return Err("No flavor configured. Set flavor in b2tf.log".red().into()),
+        }
+    } else if let Some(_matches) = matches.subcommand_matches("validate") {
+        flavor::cmd_validate(&options, &log)?;
     } else if let Some(_matches) = matches.subcommand_matches("compare") {
-        cmd_compare(&options)?;
-    } else if let Some(suse_matches) = matches.subcommand_matches("suse") {
-        let subcommand = command.find_subcommand_mut("suse").unwrap();
-        cmd_suse(&mut options, &log, subcommand, suse_matches)?;
-    } else {
+        let ret = cmd_compare(&options)?;
+        std::process::exit(ret.exit_code());
+    } else if let Some(_matches) = matches.subcommand_matches("compare-series") {
+        let ret = cmd_compare_series(&options)?;
+        std::process::exit(ret.exit_code());
+    } else if !flavor::dispatch(&mut options, &mut log, &matches)? {
         let _ = command.print_help();
     }
 
@@ -0,0 +1,150 @@
+use crate::Options;
+
+/* Describes how the upstream commit is referenced in the backport
+ *
+ * Plain        - the message is left as is
+ * CherryPick   - "(cherry picked from commit X)" at the end of the message
+ * Stable       - "commit X upstream." at the beginning of the message body
+ */
+#[derive(PartialEq, Debug)]
+pub enum MessageStyle {
+    Plain,
+    CherryPick,
+    Stable,
+}
+
+impl MessageStyle {
+    pub fn from_options(options: &Options) -> MessageStyle {
+        match options.message_style.as_deref() {
+            Some("cherry-pick") => MessageStyle::CherryPick,
+            Some("stable") => MessageStyle::Stable,
+            _ => MessageStyle::Plain,
+        }
+    }
+}
+
+pub struct Message {
+    pub subject: String,
+    pub body: String,
+}
+
+impl Message {
+    pub fn parse(text: &str) -> Message {
+        let text = text.trim();
+        let (subject, body) = match text.split_once("\n") {
+            Some((subject, body)) => (subject, body),
+            None => (text, ""),
+        };
+
+        Message {
+            subject: subject.trim().to_string(),
+            body: body.trim().to_string(),
+        }
+    }
+
+    pub fn text(&self) -> String {
+        if self.body.is_empty() {
+            return format!("{}\n", self.subject);
+        }
+        format!("{}\n\n{}\n", self.subject, self.body)
+    }
+
+    // Insert a paragraph at the beginning of the body
+    pub fn add_header(&mut self, header: &str) {
+        if self.body.is_empty() {
+            self.body = header.to_string();
+        } else {
+            self.body = format!("{}\n\n{}", header, self.body);
+        }
+    }
+
+    // Append a line to the trailer block at the end of the body
+    pub fn add_trailer(&mut self, trailer: &str) {
+        let last = self.body.split("\n\n").last().unwrap_or("");
+        let in_trailers = !last.is_empty() && last.split("\n").all(Message::is_trailer);
+
+        if self.body.is_empty() {
+            self.body = trailer.to_string();
+        } else if in_trailers {
+            self.body = format!("{}\n{}", self.body, trailer);
+        } else {
+            self.body = format!("{}\n\n{}", self.body, trailer);
+        }
+    }
+
+    pub fn contains(&self, line: &str) -> bool {
+        self.body.split("\n").any(|l| l.trim() == line)
+    }
+
+    // Trailers are "Key: value" lines, but also the [ notes ] and (cherry picked ...) lines added to them
+    fn is_trailer(line: &str) -> bool {
+        let line = line.trim();
+
+        if line.starts_with("[") || line.starts_with("(") {
+            return true;
+        }
+
+        match line.split_once(": ") {
+            Some((key, _value)) => !key.is_empty() && !key.contains(" "),
+            None => false,
+        }
+    }
+}
+
+// Replace the placeholders of a message template
+pub fn expand(template: &str, upstream: &str) -> String {
+    template.replace("{upstream}", upstream)
+            .replace("{short}", &upstream[..12.min(upstream.len())])
+}
+
+// Returns true if backported commit messages should be rewritten
+pub fn enabled(options: &Options) -> bool {
+    MessageStyle::from_options(options) != MessageStyle::Plain || !options.trailers.is_empty()
+}
+
+// Add the reference to the upstream commit in the given style
+pub fn add_reference(style: &MessageStyle, upstream: &str, msg: &mut Message) {
+    match style {
+        MessageStyle::CherryPick => {
+            let line = format!("(cherry picked from commit {})", upstream);
+            if !msg.contains(&line) {
+                msg.add_trailer(&line);
+            }
+        },
+        MessageStyle::Stable => {
+            let line = format!("commit {} upstream.", upstream);
+            if !msg.contains(&line) {
+                msg.add_header(&line);
+            }
+        },
+        MessageStyle::Plain => {},
+    }
+}
+
+// Rewrite the message of a backport of upstream. Conflicted is true if the pick needed manual resolution,
+// which adds conflict-note or "[ backport: context adjusted ]" unless conflict-note is set empty
+pub fn rewrite(options: &Options, upstream: &str, text: &str, conflicted: bool) -> String {
+    let mut msg = Message::parse(text);
+
+    add_reference(&MessageStyle::from_options(options), upstream, &mut msg);
+
+    if conflicted {
+        let note = match &options.conflict_note {
+            Some(note) => note.clone(),
+            None => "[ backport: context adjusted ]".to_string(),
+        };
+        let note = expand(&note, upstream);
+        if !note.is_empty() && !msg.contains(&note) {
+            msg.add_trailer(&note);
+        }
+    }
+
+    for trailer in options.trailers.iter() {
+        let trailer = expand(trailer, upstream);
+        if !msg.contains(&trailer) {
+            msg.add_trailer(&trailer);
+        }
+    }
+
+    msg.text()
+}
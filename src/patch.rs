@@ -1,10 +1,13 @@
 extern crate unidiff;
+use std::error::Error;
+use std::collections::HashSet;
 use colored::Colorize;
 use unidiff::{PatchSet, PatchedFile, Hunk, Line};
 
 /* Describes how well two patches match eachother
  *
  * Different    - no match at all
+ * Equivalent   - the changes are the same when ignoring whitespace, context or renamed files
  * Similar      - the changes are the same but with different line numbers
  * Same         - changes and line number are the same but patch files are not identical
  * Identical    - the patches contain exactly the same contents
@@ -12,9 +15,230 @@ use unidiff::{PatchSet, PatchedFile, Hunk, Line};
 #[derive(PartialEq, PartialOrd, Debug)]
 pub enum CompareResult {
     Different = 0,
-    Similar = 1,
-    Same = 2,
-    Identical = 3,
+    Equivalent = 1,
+    Similar = 2,
+    Same = 3,
+    Identical = 4,
+}
+
+impl CompareResult {
+    // Exit code for scripts. 0 means identical and 1 is left for errors
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CompareResult::Identical => 0,
+            CompareResult::Same => 2,
+            CompareResult::Similar => 3,
+            CompareResult::Equivalent => 4,
+            CompareResult::Different => 5,
+        }
+    }
+}
+
+/* Differences that are tolerated when looking for equivalent patches
+ *
+ * ignore_whitespace    - whitespace changes, including reflowed lines, are ignored
+ * ignore_context       - only added and removed lines are compared
+ * renames              - files may have different paths
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct CompareOptions {
+    pub ignore_whitespace: bool,
+    pub ignore_context: bool,
+    pub renames: bool,
+}
+
+impl CompareOptions {
+    pub fn semantic() -> Self {
+        Self {
+            ignore_whitespace: true,
+            ignore_context: true,
+            renames: true,
+        }
+    }
+}
+
+/* How many of the changed lines of a patch are found in another patch
+ *
+ * matched      - number of added and removed lines that were found
+ * total        - number of added and removed lines in the patch
+ * files        - breakdown per file as (path, matched, total, hunks)
+ *                where hunks is a list of (header, matched, total)
+ */
+pub type HunkSimilarity = (String, usize, usize);
+pub type FileSimilarity = (String, usize, usize, Vec<HunkSimilarity>);
+
+#[derive(Debug)]
+pub struct Similarity {
+    pub matched: usize,
+    pub total: usize,
+    pub files: Vec<FileSimilarity>,
+}
+
+impl Similarity {
+    // Returns the fraction of matched lines. An empty patch fully matches
+    pub fn score(&self) -> f64 {
+        if self.total == 0 {
+            return 1.0;
+        }
+        self.matched as f64 / self.total as f64
+    }
+
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = vec![format!("Similarity: {:.0}% ({}/{} changed lines)", self.score() * 100.0, self.matched, self.total)];
+
+        for (path, matched, total, hunks) in &self.files {
+            lines.push(format!("  {}: {}/{}", path, matched, total));
+            for (header, matched, total) in hunks {
+                lines.push(format!("    {}: {}/{}", header.cyan(), matched, total));
+            }
+        }
+
+        lines
+    }
+}
+
+// Returns the last row of the longest common subsequence table of a and b, walking both
+// backwards if rev is set. Only two rows are kept in memory
+fn lcs_row<T>(a: &[T], b: &[T], rev: bool, eq: &impl Fn(&T, &T) -> bool) -> Vec<usize> {
+    let mut prev = vec![0; b.len() + 1];
+    let mut cur = vec![0; b.len() + 1];
+
+    for i in 0..a.len() {
+        let x = if rev { &a[a.len() - 1 - i] } else { &a[i] };
+        for j in 0..b.len() {
+            let y = if rev { &b[b.len() - 1 - j] } else { &b[j] };
+            cur[j + 1] = if eq(x, y) { prev[j] + 1 } else { prev[j + 1].max(cur[j]) };
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+
+    prev
+}
+
+// Hirschberg's algorithm. Pairs are pushed in order with a_off and b_off added to the indices
+fn hirschberg<T>(a: &[T], b: &[T], a_off: usize, b_off: usize, eq: &impl Fn(&T, &T) -> bool, pairs: &mut Vec<(usize, usize)>) {
+    // Matching the common prefix and suffix directly is most of the work for similar diffs
+    let mut start = 0;
+    while start < a.len() && start < b.len() && eq(&a[start], &b[start]) {
+        pairs.push((a_off + start, b_off + start));
+        start += 1;
+    }
+
+    let mut end = 0;
+    while end < a.len() - start && end < b.len() - start && eq(&a[a.len() - 1 - end], &b[b.len() - 1 - end]) {
+        end += 1;
+    }
+
+    let a_mid = &a[start..a.len() - end];
+    let b_mid = &b[start..b.len() - end];
+    let (a_off_mid, b_off_mid) = (a_off + start, b_off + start);
+
+    if a_mid.len() == 1 {
+        if let Some(j) = b_mid.iter().position(|y| eq(&a_mid[0], y)) {
+            pairs.push((a_off_mid, b_off_mid + j));
+        }
+    } else if !a_mid.is_empty() && !b_mid.is_empty() {
+        // Split b where the LCS of the top half of a ends
+        let half = a_mid.len() / 2;
+        let top = lcs_row(&a_mid[..half], b_mid, false, eq);
+        let bottom = lcs_row(&a_mid[half..], b_mid, true, eq);
+        let split = (0..=b_mid.len()).max_by_key(|k| top[*k] + bottom[b_mid.len() - k]).unwrap_or(0);
+
+        hirschberg(&a_mid[..half], &b_mid[..split], a_off_mid, b_off_mid, eq, pairs);
+        hirschberg(&a_mid[half..], &b_mid[split..], a_off_mid + half, b_off_mid + split, eq, pairs);
+    }
+
+    for i in 0..end {
+        pairs.push((a_off + a.len() - end + i, b_off + b.len() - end + i));
+    }
+}
+
+// Align a and b by content. Returns the indices of the matching pairs in order. Uses linear
+// memory so large generated diffs can be aligned
+pub fn align<T>(a: &[T], b: &[T], eq: impl Fn(&T, &T) -> bool) -> Vec<(usize, usize)> {
+    let mut pairs = vec![];
+
+    hirschberg(a, b, 0, 0, &eq, &mut pairs);
+
+    pairs
+}
+
+// Lines without letters or digits, like braces and blank lines, are in most hunks so they
+// can't tell if two hunks are related
+fn is_significant(line: &(String, String)) -> bool {
+    line.1.chars().any(|c| c.is_alphanumeric())
+}
+
+// Returns true if most of the changed lines of the smaller hunk are found in the other
+fn hunks_related(a: &[(String, String)], b: &[(String, String)]) -> bool {
+    let significant = |lines: &[(String, String)]| lines.iter().filter(|l| is_significant(l)).count();
+    let smallest = significant(a).min(significant(b));
+
+    // Skip the alignment if the hunks have no significant line in common
+    let lines: HashSet<&(String, String)> = a.iter().filter(|l| is_significant(l)).collect();
+    if smallest > 0 && !b.iter().any(|l| lines.contains(l)) {
+        return false;
+    }
+
+    let pairs = align(a, b, |x, y| x == y);
+    if smallest == 0 {
+        return !pairs.is_empty() && pairs.len() == a.len().min(b.len());
+    }
+
+    let matched = pairs.iter().filter(|(i, _)| is_significant(&a[*i])).count();
+    matched > 0 && matched * 2 >= smallest
+}
+
+// Group the hunks of two versions of a file by content, so hunks that were reordered, split in
+// two or merged by different context end up in the same group. Returns the indices of the hunks
+// in ours and theirs of each group in order. Hunks without a counterpart are left out
+pub fn group_hunks(ours: &[PatchHunk], theirs: &[PatchHunk]) -> Vec<(Vec<usize>, Vec<usize>)> {
+    let ours_lines: Vec<Vec<(String, String)>> = ours.iter().map(|h| h.changed_lines()).collect();
+    let theirs_lines: Vec<Vec<(String, String)>> = theirs.iter().map(|h| h.changed_lines()).collect();
+
+    // Union-find over our hunks followed by their hunks
+    let mut parent: Vec<usize> = (0..ours.len() + theirs.len()).collect();
+    fn find(parent: &mut [usize], i: usize) -> usize {
+        let mut root = i;
+        while parent[root] != root {
+            root = parent[root];
+        }
+        parent[i] = root;
+        root
+    }
+
+    let mut related = vec![false; ours.len() + theirs.len()];
+    for (i, a) in ours_lines.iter().enumerate() {
+        for (j, b) in theirs_lines.iter().enumerate() {
+            if hunks_related(a, b) {
+                let (ra, rb) = (find(&mut parent, i), find(&mut parent, ours.len() + j));
+                parent[ra] = rb;
+                related[i] = true;
+                related[ours.len() + j] = true;
+            }
+        }
+    }
+
+    let mut groups: Vec<(usize, Vec<usize>, Vec<usize>)> = vec![];
+    for (i, _) in related.iter().enumerate().filter(|(_, r)| **r) {
+
+        let root = find(&mut parent, i);
+        let pos = match groups.iter().position(|g| g.0 == root) {
+            Some(pos) => pos,
+            None => {
+                groups.push((root, vec![], vec![]));
+                groups.len() - 1
+            },
+        };
+
+        if i < ours.len() {
+            groups[pos].1.push(i);
+        } else {
+            groups[pos].2.push(i - ours.len());
+        }
+    }
+
+    groups.into_iter().map(|(_, a, b)| (a, b)).collect()
 }
 
 #[derive(Clone)]
@@ -38,16 +262,6 @@ impl PatchLine {
         }
     }
 
-    pub fn print(&self) {
-        if self.line_type == "+" {
-            println!("{}{}", self.line_type.green(), self.value.green());
-        } else if self.line_type == "-" {
-            println!("{}{}", self.line_type.red(), self.value.red());
-        } else {
-            println!("{}{}", self.line_type, self.value);
-        }
-    }
-
     pub fn parse(&mut self, line: &Line) {
         self.source_line_no = line.source_line_no;
         self.target_line_no = line.target_line_no;
@@ -56,6 +270,15 @@ impl PatchLine {
         self.value = line.value.clone();
     }
 
+    // Returns the line with all runs of whitespace collapsed
+    pub fn normalized(&self) -> String {
+        self.value.split_whitespace().collect::<Vec<&str>>().join(" ")
+    }
+
+    pub fn is_change(&self) -> bool {
+        self.line_type == "+" || self.line_type == "-"
+    }
+
     pub fn compare(&self, line: &PatchLine, fuzz: bool) -> bool {
         if self.value == line.value && self.line_type == line.line_type {
             if fuzz {
@@ -93,13 +316,8 @@ impl PatchHunk {
         }
     }
 
-    pub fn print(&self) {
-        print!("{}", format!("@@ -{},{} ", self.source_start, self.source_length).cyan());
-        print!("{}", format!("+{},{} @@ ", self.target_start, self.target_length).cyan());
-        println!("{}", self.section_header);
-        for line in &self.lines {
-            line.print();
-        }
+    pub fn header(&self) -> String {
+        format!("@@ -{},{} +{},{} @@", self.source_start, self.source_length, self.target_start, self.target_length)
     }
 
     pub fn parse(&mut self, hunk: &Hunk) {
@@ -163,14 +381,82 @@ impl PatchHunk {
         }
         true
     }
+
+    // Compare the lines of the hunks without caring about whitespace or line numbers
+    pub fn compare_whitespace(&self, hunk: &PatchHunk) -> bool {
+        let lines1: Vec<&PatchLine> = self.lines.iter().filter(|l| !l.normalized().is_empty()).collect();
+        let lines2: Vec<&PatchLine> = hunk.lines.iter().filter(|l| !l.normalized().is_empty()).collect();
+
+        if lines1.len() != lines2.len() {
+            return false;
+        }
+
+        lines1.iter().zip(lines2.iter())
+            .all(|(l1, l2)| l1.line_type == l2.line_type && l1.normalized() == l2.normalized())
+    }
+
+    // Update the hunk lengths after lines were changed
+    fn update_lengths(&mut self) {
+        self.source_length = self.lines.iter().filter(|l| l.line_type != "+").count();
+        self.target_length = self.lines.iter().filter(|l| l.line_type != "-").count();
+    }
+
+    // Returns a stable identifier of the hunk based on its changed lines, so it stays the
+    // same when the hunk moves around
+    pub fn fingerprint(&self) -> String {
+        // 64-bit FNV-1a
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for (line_type, value) in self.changed_lines() {
+            for byte in line_type.bytes().chain(value.bytes()).chain("\n".bytes()) {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        format!("{:016x}", hash)
+    }
+
+    // Returns the added and removed lines as (type, normalized value)
+    pub fn changed_lines(&self) -> Vec<(String, String)> {
+        self.lines.iter()
+            .filter(|l| l.is_change())
+            .map(|l| (l.line_type.clone(), l.normalized()))
+            .collect()
+    }
+}
+
+/* What a patch does to a file as described by the git extended headers
+ *
+ * Modify       - the contents and/or mode of the file changes
+ * Create       - the file is created
+ * Delete       - the file is deleted
+ * Rename       - the file is moved and might also be modified
+ * Copy         - the file is copied and might also be modified
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub enum FileOperation {
+    Modify,
+    Create,
+    Delete,
+    Rename,
+    Copy,
 }
 
+// Git file mode of submodules
+const SUBMODULE_MODE: &str = "160000";
+
 #[derive(Clone)]
 #[derive(Debug)]
 pub struct PatchFile {
     pub source_file: String,
     pub target_file: String,
     pub hunks: Vec<PatchHunk>,
+    pub operation: FileOperation,
+    pub similarity: Option<u32>,
+    pub old_mode: Option<String>,
+    pub new_mode: Option<String>,
+    pub binary: bool,
+    pub old_blob: Option<String>,
+    pub new_blob: Option<String>,
 }
 
 impl PatchFile {
@@ -179,16 +465,163 @@ impl PatchFile {
             source_file: String::new(),
             target_file: String::new(),
             hunks: vec![],
+            operation: FileOperation::Modify,
+            similarity: None,
+            old_mode: None,
+            new_mode: None,
+            binary: false,
+            old_blob: None,
+            new_blob: None,
         }
     }
 
-    pub fn print(&self) {
-        println!("--- {}", self.source_file);
-        println!("+++ {}", self.target_file);
+    // Returns the lines describing the file that come before the hunks
+    pub fn header(&self) -> Vec<String> {
+        let mut lines = vec![];
 
-        for hunk in &self.hunks {
-            hunk.print();
+        if self.has_metadata() {
+            lines.push(format!("diff --git {} {}", self.source_file, self.target_file));
         }
+
+        match self.operation {
+            FileOperation::Create => lines.push(format!("new file mode {}", self.new_mode.clone().unwrap_or_default())),
+            FileOperation::Delete => lines.push(format!("deleted file mode {}", self.old_mode.clone().unwrap_or_default())),
+            FileOperation::Rename | FileOperation::Copy => {
+                let op = if self.operation == FileOperation::Rename { "rename" } else { "copy" };
+                if let Some(similarity) = self.similarity {
+                    lines.push(format!("similarity index {}%", similarity));
+                }
+                lines.push(format!("{} from {}", op, PatchFile::path(&self.source_file)));
+                lines.push(format!("{} to {}", op, PatchFile::path(&self.target_file)));
+            },
+            FileOperation::Modify => {},
+        }
+
+        if self.operation == FileOperation::Modify || self.operation == FileOperation::Rename || self.operation == FileOperation::Copy {
+            if let (Some(old_mode), Some(new_mode)) = (&self.old_mode, &self.new_mode) {
+                if old_mode != new_mode {
+                    lines.push(format!("old mode {}", old_mode));
+                    lines.push(format!("new mode {}", new_mode));
+                }
+            }
+        }
+
+        if self.binary {
+            lines.push(format!("Binary files {} and {} differ", self.source_file, self.target_file));
+        } else if !self.hunks.is_empty() {
+            lines.push(format!("--- {}", self.source_file));
+            lines.push(format!("+++ {}", self.target_file));
+        }
+
+        lines
+    }
+
+    // Parse the git header and extended headers of a file section of a diff.
+    // Returns None if the section doesn't start with a git header
+    pub fn parse_header(section: &str) -> Option<PatchFile> {
+        let mut lines = section.split("\n");
+        let files = lines.next()?.strip_prefix("diff --git ")?;
+        let (source, target) = files.rsplit_once(" b/")?;
+
+        let mut file = PatchFile::new();
+        file.source_file = source.to_string();
+        file.target_file = format!("b/{}", target);
+
+        for line in lines {
+            if line.starts_with("--- ") || line.starts_with("@@ ") {
+                break;
+            }
+
+            if let Some(mode) = line.strip_prefix("old mode ") {
+                file.old_mode = Some(mode.to_string());
+            } else if let Some(mode) = line.strip_prefix("new mode ") {
+                file.new_mode = Some(mode.to_string());
+            } else if let Some(mode) = line.strip_prefix("deleted file mode ") {
+                file.operation = FileOperation::Delete;
+                file.old_mode = Some(mode.to_string());
+                file.target_file = "/dev/null".to_string();
+            } else if let Some(mode) = line.strip_prefix("new file mode ") {
+                file.operation = FileOperation::Create;
+                file.new_mode = Some(mode.to_string());
+                file.source_file = "/dev/null".to_string();
+            } else if let Some(similarity) = line.strip_prefix("similarity index ") {
+                file.similarity = similarity.trim_end_matches("%").parse::<u32>().ok();
+            } else if let Some(path) = line.strip_prefix("rename from ") {
+                file.operation = FileOperation::Rename;
+                file.source_file = format!("a/{}", path);
+            } else if let Some(path) = line.strip_prefix("rename to ") {
+                file.target_file = format!("b/{}", path);
+            } else if let Some(path) = line.strip_prefix("copy from ") {
+                file.operation = FileOperation::Copy;
+                file.source_file = format!("a/{}", path);
+            } else if let Some(path) = line.strip_prefix("copy to ") {
+                file.target_file = format!("b/{}", path);
+            } else if let Some(index) = line.strip_prefix("index ") {
+                // The mode is only in the index line if it doesn't change
+                let (blobs, mode) = index.split_once(" ").unwrap_or((index, ""));
+                if let Some((old_blob, new_blob)) = blobs.split_once("..") {
+                    file.old_blob = Some(old_blob.to_string());
+                    file.new_blob = Some(new_blob.to_string());
+                }
+                if !mode.is_empty() {
+                    file.old_mode = Some(mode.to_string());
+                    file.new_mode = Some(mode.to_string());
+                }
+            } else if line.starts_with("Binary files ") || line == "GIT binary patch" {
+                file.binary = true;
+            }
+        }
+
+        Some(file)
+    }
+
+    pub fn is_submodule(&self) -> bool {
+        self.old_mode.as_deref() == Some(SUBMODULE_MODE) || self.new_mode.as_deref() == Some(SUBMODULE_MODE)
+    }
+
+    // Returns true if the file has changes that are not in the hunks
+    pub fn has_metadata(&self) -> bool {
+        self.operation != FileOperation::Modify || self.old_mode != self.new_mode || self.binary
+    }
+
+    // Diffs without git extended headers, like quilt patches, have no modes so a missing mode
+    // matches any mode
+    fn modes_match(mode1: &Option<String>, mode2: &Option<String>) -> bool {
+        match (mode1, mode2) {
+            (Some(mode1), Some(mode2)) => mode1 == mode2,
+            _ => true,
+        }
+    }
+
+    // Blob ids match if one is an abbreviation of the other, so --full-index diffs still match
+    fn blobs_match(blob1: &Option<String>, blob2: &Option<String>) -> bool {
+        match (blob1, blob2) {
+            (Some(blob1), Some(blob2)) => blob1.starts_with(blob2.as_str()) || blob2.starts_with(blob1.as_str()),
+            _ => blob1 == blob2,
+        }
+    }
+
+    // Compare the file operations, modes and binary contents. Without fuzz the similarity of
+    // renames and the original binary contents must match too
+    pub fn compare_metadata(&self, file: &PatchFile, fuzz: bool) -> bool {
+        if self.operation != file.operation || self.binary != file.binary {
+            return false;
+        }
+
+        if !PatchFile::modes_match(&self.old_mode, &file.old_mode) || !PatchFile::modes_match(&self.new_mode, &file.new_mode) {
+            return false;
+        }
+
+        if self.binary {
+            if !PatchFile::blobs_match(&self.new_blob, &file.new_blob) {
+                return false;
+            }
+            if !fuzz && !PatchFile::blobs_match(&self.old_blob, &file.old_blob) {
+                return false;
+            }
+        }
+
+        fuzz || self.similarity == file.similarity
     }
 
     pub fn parse(&mut self, file: PatchedFile) {
@@ -209,6 +642,136 @@ impl PatchFile {
                return false;
         }
 
+        if !self.compare_metadata(file, fuzz) {
+            return false;
+        }
+
+        // Submodules updated from different commits only need to end up at the same commit
+        if fuzz && self.is_submodule() && file.is_submodule() {
+            let added = |f: &PatchFile| f.changes_iter()
+                .filter(|l| l.line_type == "+")
+                .map(|l| l.value.clone())
+                .collect::<Vec<String>>();
+            return added(self) == added(file);
+        }
+
+        let same = self.hunks.len() == file.hunks.len() &&
+                   self.hunks.iter().zip(file.hunks.iter()).all(|(h1, h2)| h1.compare(h2, fuzz));
+
+        // Hunks can be split or merged by different context so without line numbers
+        // it's enough that the changed lines are the same
+        if !same && fuzz {
+            let lines1: Vec<&PatchLine> = self.changes_iter().collect();
+            let lines2: Vec<&PatchLine> = file.changes_iter().collect();
+
+            return lines1.len() == lines2.len() &&
+                   lines1.iter().zip(lines2.iter()).all(|(l1, l2)| l1.compare(l2, true));
+        }
+
+        same
+    }
+
+    fn changes_iter(&self) -> impl Iterator<Item = &PatchLine> {
+        self.hunks.iter().flat_map(|h| h.lines.iter()).filter(|l| l.is_change())
+    }
+
+    // Remove the changes found in file. Hunks are grouped by content first, so hunks that were
+    // reordered, split or merged still cancel out, and changes are only aligned within a group.
+    // Removed lines that match are kept as context, and hunks without changes left are dropped
+    pub fn subtract(&mut self, file: &PatchFile, fuzz: bool) {
+        let mut matched: HashSet<(usize, usize)> = HashSet::new();
+
+        for (mine, theirs) in group_hunks(&self.hunks, &file.hunks) {
+            let theirs: Vec<&PatchLine> = theirs.iter()
+                .flat_map(|h| file.hunks[*h].lines.iter())
+                .filter(|l| l.is_change())
+                .collect();
+
+            let mut positions: Vec<(usize, usize)> = vec![];
+            let mut ours: Vec<&PatchLine> = vec![];
+            for h in mine {
+                for (l, line) in self.hunks[h].lines.iter().enumerate() {
+                    if line.is_change() {
+                        positions.push((h, l));
+                        ours.push(line);
+                    }
+                }
+            }
+
+            for (i, _) in align(&ours, &theirs, |a, b| a.compare(b, fuzz)) {
+                matched.insert(positions[i]);
+            }
+        }
+
+        for (h, hunk) in self.hunks.iter_mut().enumerate() {
+            let mut lines = vec![];
+
+            for (l, line) in hunk.lines.iter().enumerate() {
+                if !matched.contains(&(h, l)) {
+                    lines.push(line.clone());
+                } else if line.line_type == "-" {
+                    let mut context = line.clone();
+                    context.line_type = " ".to_string();
+                    lines.push(context);
+                }
+            }
+
+            hunk.lines = lines;
+            hunk.update_lengths();
+        }
+
+        self.hunks.retain(|h| h.lines.iter().any(|l| l.is_change()));
+    }
+
+    // Strip the a/ and b/ prefixes from a file name
+    pub fn path(name: &str) -> &str {
+        name.strip_prefix("a/").or(name.strip_prefix("b/")).unwrap_or(name)
+    }
+
+    // Returns the removed and added lines of all hunks. When ignoring whitespace all whitespace
+    // is dropped so that lines that were reflowed or reindented still match
+    pub fn changes(&self, ignore_whitespace: bool) -> (String, String) {
+        let mut removed = String::new();
+        let mut added = String::new();
+
+        for line in self.hunks.iter().flat_map(|h| h.lines.iter()) {
+            let value = if ignore_whitespace {
+                line.value.split_whitespace().collect::<String>()
+            } else {
+                format!("{}\n", line.value)
+            };
+
+            if line.line_type == "-" {
+                removed.push_str(&value);
+            } else if line.line_type == "+" {
+                added.push_str(&value);
+            }
+        }
+
+        (removed, added)
+    }
+
+    // Returns true if the files make the same changes with the given differences tolerated
+    pub fn compare_semantic(&self, file: &PatchFile, opts: &CompareOptions) -> bool {
+        if !opts.renames && (PatchFile::path(&self.source_file) != PatchFile::path(&file.source_file) ||
+                             PatchFile::path(&self.target_file) != PatchFile::path(&file.target_file)) {
+            return false;
+        }
+
+        // A renamed file might be modified instead, but all other changes must match
+        let renamed = |f: &PatchFile| f.operation == FileOperation::Rename || f.operation == FileOperation::Modify;
+        if !(opts.renames && renamed(self) && renamed(file)) && self.operation != file.operation {
+            return false;
+        }
+        if self.binary != file.binary || (self.binary && !PatchFile::blobs_match(&self.new_blob, &file.new_blob)) ||
+           !PatchFile::modes_match(&self.new_mode, &file.new_mode) {
+            return false;
+        }
+
+        if opts.ignore_context {
+            return self.changes(opts.ignore_whitespace) == file.changes(opts.ignore_whitespace);
+        }
+
         if self.hunks.len() != file.hunks.len() {
             return false;
         }
@@ -216,7 +779,8 @@ impl PatchFile {
         for i in 0..self.hunks.len() {
             let h1 = &self.hunks[i];
             let h2 = &file.hunks[i];
-            if !h1.compare(h2, fuzz) {
+            let same = if opts.ignore_whitespace { h1.compare_whitespace(h2) } else { h1.compare(h2, true) };
+            if !same {
                 return false;
             }
         }
@@ -237,37 +801,75 @@ impl Patch {
         }
     }
 
-    pub fn print(&self) {
-        for file in &self.files {
-            file.print();
-        }
+    // Git leaves out hunk lengths of 1 but unidiff reads them as 0, so add them back
+    fn normalize_hunk_header(line: &str) -> String {
+        let Some(rest) = line.strip_prefix("@@ -") else {
+            return line.to_string();
+        };
+        let Some((ranges, header)) = rest.split_once(" @@") else {
+            return line.to_string();
+        };
+        let Some((source, target)) = ranges.split_once(" +") else {
+            return line.to_string();
+        };
+
+        let add_length = |range: &str| if range.contains(",") { range.to_string() } else { format!("{},1", range) };
+
+        format!("@@ -{} +{} @@{}", add_length(source), add_length(target), header)
     }
 
-    pub fn parse(&mut self, diff: &String) {
-        let mut unidiff = PatchSet::new();
-        unidiff.parse(diff).expect("Error parsing diff with unidiff");
+    pub fn parse(&mut self, diff: &str) -> Result<(), Box<dyn Error>> {
+        let diff: String = diff.split("\n")
+            .map(Patch::normalize_hunk_header)
+            .collect::<Vec<String>>()
+            .join("\n");
 
-        for file in unidiff {
-            let mut f = PatchFile::new();
-            f.parse(file);
-            self.files.push(f);
+        // Split the diff on git headers so that files without hunks, like binaries, renames and
+        // mode changes, are found too. Anything before the first git header is parsed on its own
+        let mut sections: Vec<String> = vec![];
+        for line in diff.split("\n") {
+            if sections.is_empty() || line.starts_with("diff --git ") {
+                sections.push(String::new());
+            }
+            let section = sections.last_mut().unwrap();
+            section.push_str(line);
+            section.push('\n');
         }
+
+        for section in sections {
+            let mut unidiff = PatchSet::new();
+            if let Err(e) = unidiff.parse(&section) {
+                return Err(format!("Error parsing diff: {:?}", e).into());
+            }
+
+            match PatchFile::parse_header(&section) {
+                Some(mut f) => {
+                    if let Some(file) = unidiff.into_iter().next() {
+                        f.parse(file);
+                    }
+                    self.files.push(f);
+                },
+                None => {
+                    for file in unidiff {
+                        let mut f = PatchFile::new();
+                        f.parse(file);
+                        self.files.push(f);
+                    }
+                },
+            }
+        }
+
+        Ok(())
     }
 
     pub fn subtract(&mut self, patch: Patch, fuzz: bool) {
         // Iterate over subtrahend files
-        let mut i = 0;
-        let i_len = patch.files.len();
-
-        while i < i_len {
-            let file_a = patch.files[i].clone();
-
+        for file_a in patch.files.iter() {
             // Iterate over minuend files
             let mut j = 0;
-            let mut j_len = self.files.len();
 
-            while j < j_len {
-                let file_b = self.files[j].clone();
+            while j < self.files.len() {
+                let file_b = &mut self.files[j];
 
                 if file_a.source_file != file_b.source_file ||
                    file_a.target_file != file_b.target_file {
@@ -276,51 +878,189 @@ impl Patch {
                 }
 
                 // Remove entire file if "Similar"
-                if file_a.compare(&file_b, fuzz) {
+                if file_a.compare(file_b, fuzz) {
+                    self.files.remove(j);
+                    continue;
+                }
+
+                file_b.subtract(file_a, fuzz);
+
+                // Remove file if empty and nothing but the hunks changed
+                if file_b.hunks.is_empty() && (!file_b.has_metadata() || file_b.compare_metadata(file_a, fuzz)) {
                     self.files.remove(j);
-                    j_len -= 1;
                     continue;
                 }
 
-                // Iterate over subtrahend hunks
-                let mut k = 0;
-                let k_len = patch.files[i].hunks.len();
+                j += 1;
+            }
+        }
+    }
+
+    pub fn compare(&self, patch: Patch) -> CompareResult {
+        match self.compare_exact(&patch) {
+            CompareResult::Different => {
+                if self.compare_semantic(&patch, &CompareOptions::semantic()) {
+                    return CompareResult::Equivalent;
+                }
+                CompareResult::Different
+            },
+            res => res,
+        }
+    }
+
+    // Returns true if the patches make the same changes with the given differences tolerated.
+    // Files are paired by path, and with renames allowed, remaining files are paired in order
+    pub fn compare_semantic(&self, patch: &Patch, opts: &CompareOptions) -> bool {
+        // Without context, files that don't add or remove lines don't matter
+        let keep = |f: &&PatchFile| !opts.ignore_context || f.has_metadata() ||
+                                    f.changes(opts.ignore_whitespace) != (String::new(), String::new());
+        let files1: Vec<&PatchFile> = self.files.iter().filter(keep).collect();
+        let mut files2: Vec<&PatchFile> = patch.files.iter().filter(keep).collect();
 
-                while k < k_len {
-                    let hunk_a = &file_a.hunks[k];
+        if files1.len() != files2.len() {
+            return false;
+        }
 
-                    // Iterate over minuend hunks
-                    let mut l = 0;
-                    let mut l_len = self.files[j].hunks.len();
+        let mut unpaired: Vec<&PatchFile> = vec![];
+        for file in files1 {
+            let pos = files2.iter().position(|f| PatchFile::path(&f.target_file) == PatchFile::path(&file.target_file));
+            match pos {
+                Some(pos) => {
+                    if !file.compare_semantic(files2.remove(pos), opts) {
+                        return false;
+                    }
+                },
+                None => unpaired.push(file),
+            }
+        }
 
-                    while l < l_len {
-                        let hunk_b = &file_b.hunks[l];
+        if !unpaired.is_empty() && !opts.renames {
+            return false;
+        }
 
-                        if hunk_a.compare(hunk_b, fuzz) {
-                            self.files[j].hunks.remove(l);
-                            l_len -= 1;
-                            continue;
-                        }
+        unpaired.iter().zip(files2.iter()).all(|(f1, f2)| f1.compare_semantic(f2, opts))
+    }
+
+    // Returns how much of this patch is found in patch. Files are paired by path and hunks are
+    // grouped by content, so reordered, split or merged hunks still match
+    pub fn similarity(&self, patch: &Patch) -> Similarity {
+        let mut sim = Similarity { matched: 0, total: 0, files: vec![] };
 
-                        l += 1;
+        for file in &self.files {
+            let path = PatchFile::path(&file.target_file).to_string();
+            let mut others: Vec<&PatchFile> = patch.files.iter()
+                .filter(|f| PatchFile::path(&f.target_file) == path)
+                .collect();
+
+            // The file might have been renamed so fall back to matching on the file name
+            if others.is_empty() {
+                let name = path.rsplit("/").next().unwrap_or_default();
+                others = patch.files.iter()
+                    .filter(|f| f.target_file.rsplit("/").next().unwrap_or_default() == name)
+                    .collect();
+            }
+
+            let theirs: Vec<PatchHunk> = others.iter().flat_map(|f| f.hunks.iter().cloned()).collect();
+            let mut hunk_matched = vec![0; file.hunks.len()];
+
+            // Lines are only aligned within hunks of the same group so each line counts once
+            for (mine, other) in group_hunks(&file.hunks, &theirs) {
+                let mut owners: Vec<usize> = vec![];
+                let mut lines: Vec<(String, String)> = vec![];
+                for h in mine {
+                    for line in file.hunks[h].changed_lines() {
+                        owners.push(h);
+                        lines.push(line);
                     }
-                    k += 1;
                 }
+                let other: Vec<(String, String)> = other.iter().flat_map(|h| theirs[*h].changed_lines()).collect();
 
-                // Remove file if empty
-                if self.files[j].hunks.is_empty() {
-                    self.files.remove(j);
-                    j_len -= 1;
+                for (i, _) in align(&lines, &other, |x, y| x == y) {
+                    hunk_matched[owners[i]] += 1;
+                }
+            }
+
+            let hunks: Vec<HunkSimilarity> = file.hunks.iter().zip(hunk_matched.iter())
+                .map(|(h, matched)| (h.header(), *matched, h.changed_lines().len()))
+                .collect();
+            let matched: usize = hunk_matched.iter().sum();
+            let total: usize = hunks.iter().map(|h| h.2).sum();
+
+            sim.matched += matched;
+            sim.total += total;
+            sim.files.push((path, matched, total, hunks));
+        }
+
+        sim
+    }
+
+    // Returns the reasons the patches are not the same, one per file or hunk
+    pub fn differences(&self, patch: &Patch) -> Vec<String> {
+        let mut reasons = vec![];
+        let path = |f: &PatchFile| {
+            let name = if f.target_file == "/dev/null" { &f.source_file } else { &f.target_file };
+            PatchFile::path(name).to_string()
+        };
+
+        for file in &self.files {
+            let other = match patch.files.iter().find(|f| path(f) == path(file)) {
+                Some(other) => other,
+                None => {
+                    reasons.push(format!("{}: only in the first patch", path(file)));
                     continue;
+                },
+            };
+
+            if file.compare(other, false) {
+                continue;
+            }
+
+            let renamed = |f: &PatchFile| f.operation == FileOperation::Rename || f.operation == FileOperation::Copy;
+            if file.source_file != other.source_file && renamed(file) && renamed(other) {
+                reasons.push(format!("{}: renamed from {} instead of {}", path(file),
+                                     PatchFile::path(&file.source_file), PatchFile::path(&other.source_file)));
+            }
+            if !file.compare_metadata(other, true) {
+                reasons.push(format!("{}: file operation, mode or binary contents differ ({:?} {} vs {:?} {})", path(file),
+                                     file.operation, file.new_mode.clone().unwrap_or_default(),
+                                     other.operation, other.new_mode.clone().unwrap_or_default()));
+                continue;
+            }
+            if file.compare(other, true) {
+                reasons.push(format!("{}: same changes at other lines", path(file)));
+                continue;
+            }
+
+            // Explain each hunk, pairing them by content
+            let mut unpaired: Vec<&PatchHunk> = other.hunks.iter().collect();
+            for hunk in &file.hunks {
+                if let Some(pos) = unpaired.iter().position(|h| hunk.compare(h, false)) {
+                    unpaired.remove(pos);
+                } else if let Some(pos) = unpaired.iter().position(|h| hunk.compare(h, true)) {
+                    reasons.push(format!("{}: {} moved to {}", path(file), hunk.header(), unpaired[pos].header()));
+                    unpaired.remove(pos);
+                } else if let Some(pos) = unpaired.iter().position(|h| hunk.compare_whitespace(h)) {
+                    reasons.push(format!("{}: {} differs in whitespace from {}", path(file), hunk.header(), unpaired[pos].header()));
+                    unpaired.remove(pos);
+                } else {
+                    reasons.push(format!("{}: {} is not in the second patch", path(file), hunk.header()));
                 }
+            }
+            for hunk in unpaired {
+                reasons.push(format!("{}: {} is not in the first patch", path(file), hunk.header()));
+            }
+        }
 
-                j += 1;
+        for file in &patch.files {
+            if !self.files.iter().any(|f| path(f) == path(file)) {
+                reasons.push(format!("{}: only in the second patch", path(file)));
             }
-            i += 1;
         }
+
+        reasons
     }
 
-    pub fn compare(&self, patch: Patch) -> CompareResult {
+    fn compare_exact(&self, patch: &Patch) -> CompareResult {
         if self.files.len() != patch.files.len() {
             return CompareResult::Different;
         }
@@ -344,3 +1084,168 @@ impl Patch {
         CompareResult::Same
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_file(diff: &str) -> PatchFile {
+        let mut patch = Patch::new();
+        patch.parse(diff).unwrap();
+        patch.files.remove(0)
+    }
+
+    fn changes(file: &PatchFile) -> Vec<String> {
+        file.changes_iter().map(|l| format!("{}{}", l.line_type, l.value)).collect()
+    }
+
+    const ONE_HUNK: &str = "diff --git a/f.c b/f.c
+--- a/f.c
++++ b/f.c
+@@ -1,7 +1,9 @@
+ int a;
++int b;
+ int c;
+ int d;
+ int e;
+ int f;
++int g;
+ int h;
+ int i;
+";
+
+    const TWO_HUNKS: &str = "diff --git a/f.c b/f.c
+--- a/f.c
++++ b/f.c
+@@ -1,2 +1,3 @@
+ int a;
++int b;
+ int c;
+@@ -5,2 +6,3 @@
+ int f;
++int g;
+ int h;
+";
+
+    const REORDERED: &str = "diff --git a/f.c b/f.c
+--- a/f.c
++++ b/f.c
+@@ -1,2 +1,3 @@
+ int f;
++int g;
+ int h;
+@@ -20,2 +21,3 @@
+ int a;
++int b;
+ int c;
+";
+
+    #[test]
+    fn align_linear() {
+        let a: Vec<char> = "ABCBDAB".chars().collect();
+        let b: Vec<char> = "BDCABA".chars().collect();
+        let pairs = align(&a, &b, |x, y| x == y);
+
+        assert_eq!(pairs.len(), 4);
+        assert!(pairs.windows(2).all(|p| p[0].0 < p[1].0 && p[0].1 < p[1].1));
+        assert!(pairs.iter().all(|(i, j)| a[*i] == b[*j]));
+    }
+
+    #[test]
+    fn subtract_split_hunk() {
+        let mut file = parse_file(ONE_HUNK);
+        file.subtract(&parse_file(TWO_HUNKS), true);
+
+        assert!(file.hunks.is_empty());
+    }
+
+    #[test]
+    fn subtract_merged_hunks() {
+        let mut file = parse_file(TWO_HUNKS);
+        file.subtract(&parse_file(ONE_HUNK), true);
+
+        assert!(file.hunks.is_empty());
+    }
+
+    #[test]
+    fn subtract_reordered_hunks() {
+        let mut file = parse_file(TWO_HUNKS);
+        file.subtract(&parse_file(REORDERED), true);
+
+        assert!(file.hunks.is_empty());
+    }
+
+    #[test]
+    fn subtract_keeps_unrelated_hunks() {
+        let mut file = parse_file("diff --git a/f.c b/f.c
+--- a/f.c
++++ b/f.c
+@@ -1,2 +1,4 @@
+ int a;
++\tfoo();
++}
+ int c;
+");
+        file.subtract(&parse_file("diff --git a/f.c b/f.c
+--- a/f.c
++++ b/f.c
+@@ -30,2 +30,4 @@
+ int x;
++\tbar();
++}
+ int y;
+"), true);
+
+        assert_eq!(changes(&file), vec!["+\tfoo();", "+}"]);
+    }
+
+    #[test]
+    fn group_split_hunk() {
+        let one = parse_file(ONE_HUNK);
+        let two = parse_file(TWO_HUNKS);
+
+        assert_eq!(group_hunks(&one.hunks, &two.hunks), vec![(vec![0], vec![0, 1])]);
+        assert_eq!(group_hunks(&two.hunks, &parse_file(REORDERED).hunks), vec![(vec![0], vec![1]), (vec![1], vec![0])]);
+    }
+
+    #[test]
+    fn compare_without_extended_headers() {
+        let git = parse_file("diff --git a/f.c b/f.c
+index 1234567..89abcde 100644
+--- a/f.c
++++ b/f.c
+@@ -1,2 +1,3 @@
+ int a;
++int b;
+ int c;
+");
+        let quilt = parse_file("--- a/f.c
++++ b/f.c
+@@ -1,2 +1,3 @@
+ int a;
++int b;
+ int c;
+");
+
+        assert!(git.compare(&quilt, false));
+        assert!(git.compare_semantic(&quilt, &CompareOptions::semantic()));
+    }
+
+    #[test]
+    fn compare_abbreviated_blobs() {
+        let short = parse_file("diff --git a/fw.bin b/fw.bin
+index 1234567..89abcde 100644
+Binary files a/fw.bin and b/fw.bin differ
+");
+        let full = parse_file("diff --git a/fw.bin b/fw.bin
+index 1234567890123456789012345678901234567890..89abcdef01234567890123456789012345678901 100644
+Binary files a/fw.bin and b/fw.bin differ
+");
+
+        assert!(short.compare_metadata(&full, false));
+        assert!(!short.compare_metadata(&parse_file("diff --git a/fw.bin b/fw.bin
+index 1234567..89abcdf 100644
+Binary files a/fw.bin and b/fw.bin differ
+"), false));
+    }
+}
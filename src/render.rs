@@ -0,0 +1,277 @@
+use std::env;
+use std::error::Error;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+use colored::{ColoredString, Colorize};
+use crate::Options;
+use crate::patch::{align, Patch, PatchFile, PatchHunk, PatchLine};
+
+/* How diffs are laid out
+ *
+ * Unified      - removed and added lines below eachother like git diff
+ * SideBySide   - removed lines to the left and added lines to the right
+ */
+#[derive(PartialEq, Debug)]
+pub enum Layout {
+    Unified,
+    SideBySide,
+}
+
+impl Layout {
+    pub fn from_options(options: &Options) -> Layout {
+        match options.layout.as_deref() {
+            Some("side-by-side") => Layout::SideBySide,
+            _ => Layout::Unified,
+        }
+    }
+}
+
+// Decide if output is colored. NO_COLOR and --color=never turn it off, --color=always forces it
+pub fn setup_color(options: &Options) {
+    let color = match options.color.as_deref() {
+        Some("never") => false,
+        Some("always") => true,
+        _ => env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && io::stdout().is_terminal(),
+    };
+
+    colored::control::set_override(color);
+}
+
+// Split a line into words, runs of whitespace and single punctuation characters
+fn tokenize(line: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = vec![];
+    let class = |c: char| if c.is_alphanumeric() || c == '_' { 0 } else if c.is_whitespace() { 1 } else { 2 };
+
+    for c in line.chars() {
+        match tokens.last_mut() {
+            Some(last) if class(c) != 2 && last.chars().last().map(class) == Some(class(c)) => last.push(c),
+            _ => tokens.push(c.to_string()),
+        }
+    }
+
+    tokens
+}
+
+// Returns the tokens of old and new lines, flagged if they changed
+type Tokens = Vec<(String, bool)>;
+
+fn word_diff(old: &str, new: &str) -> (Tokens, Tokens) {
+    let a = tokenize(old);
+    let b = tokenize(new);
+    let pairs = align(&a, &b, |x, y| x == y);
+
+    let mut old_tokens: Tokens = a.iter().map(|t| (t.clone(), true)).collect();
+    let mut new_tokens: Tokens = b.iter().map(|t| (t.clone(), true)).collect();
+    for (i, j) in pairs {
+        old_tokens[i].1 = false;
+        new_tokens[j].1 = false;
+    }
+
+    (old_tokens, new_tokens)
+}
+
+pub struct Renderer {
+    layout: Layout,
+    width: usize,
+    pager: String,
+    buf: String,
+}
+
+impl Renderer {
+    pub fn new(options: &Options) -> Renderer {
+        let columns = env::var("COLUMNS").ok().and_then(|c| c.parse::<usize>().ok()).unwrap_or(160);
+        let pager = match &options.pager {
+            Some(pager) => pager.clone(),
+            None => env::var("PAGER").unwrap_or("less".to_string()),
+        };
+
+        Renderer {
+            layout: Layout::from_options(options),
+            width: columns.max(40),
+            pager,
+            buf: String::new(),
+        }
+    }
+
+    pub fn line(&mut self, line: &str) {
+        self.buf.push_str(line);
+        self.buf.push('\n');
+    }
+
+    pub fn patch(&mut self, patch: &Patch) {
+        for file in &patch.files {
+            self.file(file);
+        }
+    }
+
+    pub fn file(&mut self, file: &PatchFile) {
+        self.file_header(file);
+
+        for hunk in &file.hunks {
+            self.hunk(hunk);
+        }
+    }
+
+    pub fn file_header(&mut self, file: &PatchFile) {
+        for line in file.header() {
+            self.line(&line.bold().to_string());
+        }
+    }
+
+    pub fn hunk(&mut self, hunk: &PatchHunk) {
+        if hunk.section_header.is_empty() {
+            self.line(&hunk.header().cyan().to_string());
+        } else {
+            self.line(&format!("{} {}", hunk.header().cyan(), hunk.section_header));
+        }
+
+        // Pair up runs of removed lines with the added lines that follow them
+        let mut i = 0;
+        while i < hunk.lines.len() {
+            let line = &hunk.lines[i];
+
+            if line.line_type != "-" && line.line_type != "+" {
+                self.row(Some(line), Some(line));
+                i += 1;
+                continue;
+            }
+
+            let removed: Vec<&PatchLine> = hunk.lines[i..].iter().take_while(|l| l.line_type == "-").collect();
+            let added: Vec<&PatchLine> = hunk.lines[i + removed.len()..].iter().take_while(|l| l.line_type == "+").collect();
+            i += removed.len() + added.len();
+
+            match self.layout {
+                Layout::Unified => {
+                    let pairs = removed.len().min(added.len());
+                    let diffs: Vec<(Tokens, Tokens)> = (0..pairs).map(|k| word_diff(&removed[k].value, &added[k].value)).collect();
+
+                    for (k, line) in removed.iter().enumerate() {
+                        let tokens = diffs.get(k).map(|d| d.0.clone());
+                        self.unified(line, tokens);
+                    }
+                    for (k, line) in added.iter().enumerate() {
+                        let tokens = diffs.get(k).map(|d| d.1.clone());
+                        self.unified(line, tokens);
+                    }
+                },
+                Layout::SideBySide => {
+                    for k in 0..removed.len().max(added.len()) {
+                        self.row(removed.get(k).copied(), added.get(k).copied());
+                    }
+                },
+            }
+        }
+    }
+
+    fn paint(line_type: &str, text: &str, changed: bool) -> ColoredString {
+        let painted = match line_type {
+            "-" => text.red(),
+            "+" => text.green(),
+            _ => text.normal(),
+        };
+
+        if changed { painted.reversed() } else { painted }
+    }
+
+    // Render tokens cut or padded to width. Changed tokens are highlighted if some tokens
+    // are unchanged, otherwise the lines have nothing in common and highlighting is just noise
+    fn tokens(line_type: &str, tokens: &Tokens, width: Option<usize>) -> String {
+        let highlight = tokens.iter().any(|t| !t.1);
+        let mut out = String::new();
+        let mut run = String::new();
+        let mut run_changed = false;
+        let mut len = 0;
+
+        for (token, changed) in tokens {
+            let changed = highlight && *changed;
+
+            // Tabs would break the alignment of the columns
+            let token = match width {
+                Some(width) => {
+                    let token = token.replace("\t", "    ");
+                    token.chars().take(width - len).collect()
+                },
+                None => token.clone(),
+            };
+
+            // Paint runs of tokens at once to keep the escape codes down
+            if changed != run_changed && !run.is_empty() {
+                out.push_str(&Renderer::paint(line_type, &run, run_changed).to_string());
+                run.clear();
+            }
+            run_changed = changed;
+            run.push_str(&token);
+            len += token.chars().count();
+
+            if width.is_some_and(|w| len >= w) {
+                break;
+            }
+        }
+
+        if !run.is_empty() {
+            out.push_str(&Renderer::paint(line_type, &run, run_changed).to_string());
+        }
+
+        if let Some(width) = width {
+            out.push_str(&" ".repeat(width.saturating_sub(len)));
+        }
+
+        out
+    }
+
+    fn unified(&mut self, line: &PatchLine, tokens: Option<Tokens>) {
+        let tokens = tokens.unwrap_or(vec![(line.value.clone(), false)]);
+        let text = format!("{}{}", Renderer::paint(&line.line_type, &line.line_type, false),
+                           Renderer::tokens(&line.line_type, &tokens, None));
+        self.line(&text);
+    }
+
+    fn row(&mut self, left: Option<&PatchLine>, right: Option<&PatchLine>) {
+        if self.layout == Layout::Unified {
+            if let Some(line) = left.or(right) {
+                self.unified(line, None);
+            }
+            return;
+        }
+
+        let width = (self.width - 3) / 2;
+        let (old, new) = match (left, right) {
+            (Some(l), Some(r)) if l.line_type != r.line_type => word_diff(&l.value, &r.value),
+            _ => (left.map(|l| vec![(l.value.clone(), false)]).unwrap_or_default(),
+                  right.map(|r| vec![(r.value.clone(), false)]).unwrap_or_default()),
+        };
+
+        let left_type = left.map(|l| l.line_type.as_str()).unwrap_or(" ");
+        let right_type = right.map(|r| r.line_type.as_str()).unwrap_or(" ");
+        let separator = if left_type == " " && right_type == " " { " | " } else { " ! " };
+
+        let text = format!("{}{}{}", Renderer::tokens(left_type, &old, Some(width)), separator,
+                           Renderer::tokens(right_type, &new, Some(width)));
+        self.line(text.trim_end());
+    }
+
+    // Write the output, through the pager if we're on a terminal
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        if !io::stdout().is_terminal() || self.pager.is_empty() || self.pager == "cat" {
+            print!("{}", self.buf);
+            return Ok(());
+        }
+
+        // Like git, let less handle colors and quit if everything fits on one screen
+        let less = env::var("LESS").unwrap_or("FRX".to_string());
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.pager)
+            .env("LESS", less)
+            .stdin(Stdio::piped())
+            .spawn()?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            // The user might quit the pager before reading everything
+            let _ = stdin.write_all(self.buf.as_bytes());
+        }
+        child.wait()?;
+
+        Ok(())
+    }
+}
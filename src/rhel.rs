@@ -0,0 +1,109 @@
+use std::error::Error;
+use colored::Colorize;
+use crate::Options;
+use crate::Log;
+use crate::flavor;
+use crate::flavor::Flavor;
+use crate::message::Message;
+
+/* Red Hat and CentOS Stream backports. The tickets come from the jira and bugzilla config keys
+ *
+ * JIRA: https://issues.redhat.com/browse/RHEL-1234
+ * Bugzilla: https://bugzilla.redhat.com/1234
+ * Upstream Status: v6.8-rc1
+ * Conflicts: drivers/foo.c
+ *
+ * commit <upstream hash>
+ *
+ * <upstream message>
+ */
+pub struct Rhel {
+}
+
+// Tickets can be given as keys or as full links
+fn ticket_lines(options: &Options) -> Vec<String> {
+    let mut lines = vec![];
+
+    for jira in options.flavor_value("jira").unwrap_or_default().split_whitespace() {
+        if jira.starts_with("http") {
+            lines.push(format!("JIRA: {}", jira));
+        } else {
+            lines.push(format!("JIRA: https://issues.redhat.com/browse/{}", jira));
+        }
+    }
+
+    for bz in options.flavor_value("bugzilla").unwrap_or_default().split_whitespace() {
+        if bz.starts_with("http") {
+            lines.push(format!("Bugzilla: {}", bz));
+        } else {
+            lines.push(format!("Bugzilla: https://bugzilla.redhat.com/{}", bz));
+        }
+    }
+
+    lines
+}
+
+impl Flavor for Rhel {
+    fn name(&self) -> &str {
+        "rhel"
+    }
+
+    fn config_keys(&self) -> Vec<(&'static str, &'static str)> {
+        vec![
+            ("jira", "JIRA issues the backport is for"),
+            ("bugzilla", "Bugzilla bugs the backport is for"),
+        ]
+    }
+
+    fn tag_message(&self, options: &Options, log: &Log, upstream: &str, text: &str) -> Result<String, Box<dyn Error>> {
+        let git_dir = options.git_dir.clone().unwrap();
+        let mut msg = Message::parse(text);
+
+        let commit = format!("commit {}", upstream);
+        if !msg.contains(&commit) {
+            msg.add_header(&commit);
+        }
+
+        let mut tags = ticket_lines(options);
+        if tags.is_empty() {
+            println!("{}", "No jira or bugzilla configured, the commit references no ticket".yellow());
+        }
+
+        tags.push(format!("Upstream Status: {}", flavor::mainline_tag(upstream, log, &git_dir)?));
+
+        // Tell reviewers which files had to be resolved by hand
+        if let Some(conflicts) = log.get_annotation(upstream, "conflicts") {
+            tags.push(format!("Conflicts: {}", conflicts.split_whitespace().collect::<Vec<&str>>().join(", ")));
+        }
+
+        let tags: Vec<String> = tags.into_iter().filter(|t| !msg.contains(t)).collect();
+        if !tags.is_empty() {
+            msg.add_header(&tags.join("\n"));
+        }
+
+        Ok(msg.text())
+    }
+
+    fn validate(&self, _options: &Options, _log: &Log, upstream: Option<&str>, text: &str) -> Vec<String> {
+        let msg = Message::parse(text);
+        let lines: Vec<&str> = msg.body.split("\n").map(|l| l.trim()).collect();
+        let mut problems = vec![];
+
+        if !lines.iter().any(|l| l.starts_with("JIRA:") || l.starts_with("Bugzilla:")) {
+            problems.push("No JIRA: or Bugzilla: ticket".to_string());
+        }
+
+        if !lines.iter().any(|l| l.starts_with("Upstream Status:")) {
+            problems.push("No Upstream Status: line".to_string());
+        }
+
+        // Downstream only commits have no upstream commit to reference
+        if let Some(upstream) = upstream {
+            if !msg.contains(&format!("commit {}", upstream)) {
+                problems.push(format!("No commit {} line", upstream));
+            }
+        }
+
+        problems
+    }
+}
@@ -0,0 +1,183 @@
+use std::fs;
+use std::path::Path;
+use std::error::Error;
+use colored::Colorize;
+use crate::Options;
+use crate::git::Git;
+
+/* A patch of a series. Patches of two series are paired by the hashes they reference
+ * and otherwise by subject
+ *
+ * name     - file name, or position in the mbox
+ * subject  - subject without the [PATCH ...] prefix
+ * hashes   - upstream references (Git-commit, cherry picked from, commit X upstream)
+ *            followed by the hash of the From line
+ * text     - the whole patch
+ */
+pub struct SeriesPatch {
+    pub name: String,
+    pub subject: String,
+    pub hashes: Vec<String>,
+    pub text: String,
+}
+
+fn is_hash(word: &str) -> bool {
+    word.len() == 40 && word.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+impl SeriesPatch {
+    fn parse(name: &str, text: &str) -> SeriesPatch {
+        let mut subject = String::new();
+        let mut upstream: Vec<String> = vec![];
+        let mut from: Vec<String> = vec![];
+        let mut in_subject = false;
+
+        for line in text.split("\n") {
+            // The diff itself can't carry any references
+            if line.starts_with("diff --git ") {
+                break;
+            }
+
+            // Subjects can be folded over multiple lines
+            if in_subject && line.starts_with([' ', '\t']) {
+                subject.push_str(line.trim_end());
+                continue;
+            }
+            in_subject = false;
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            if subject.is_empty() && line.starts_with("Subject:") {
+                subject = line["Subject:".len()..].trim().to_string();
+                in_subject = true;
+            } else if line.starts_with("From ") && words.len() > 1 && is_hash(words[1]) {
+                from.push(words[1].to_string());
+            } else if line.starts_with("Git-commit:") && words.len() > 1 {
+                upstream.push(words[1].to_string());
+            } else if line.starts_with("(cherry picked from commit ") || line.starts_with("(backported from commit ") {
+                let pos = words.iter().position(|w| *w == "commit").unwrap();
+                if let Some(hash) = words.get(pos + 1) {
+                    upstream.push(hash.trim_end_matches(")").to_string());
+                }
+            } else if words.len() == 3 && words[0] == "commit" && words[2] == "upstream." {
+                upstream.push(words[1].to_string());
+            }
+        }
+
+        // Drop the [PATCH v2 1/3] prefix
+        let subject = match subject.strip_prefix("[") {
+            Some(rest) if rest.contains("]") => rest.split_once("]").unwrap().1.trim().to_string(),
+            _ => subject,
+        };
+
+        upstream.extend(from);
+
+        SeriesPatch {
+            name: name.to_string(),
+            subject,
+            hashes: upstream,
+            text: text.to_string(),
+        }
+    }
+
+    // Hashes might be abbreviated so compare on the shortest length
+    fn same_commit(&self, other: &SeriesPatch) -> bool {
+        self.hashes.iter().any(|a| other.hashes.iter().any(|b| {
+            let len = a.len().min(b.len());
+            len >= 12 && a.get(..len) == b.get(..len)
+        }))
+    }
+}
+
+// Messages in an mbox start with a From line after an empty line
+fn split_mbox(text: &str) -> Vec<String> {
+    let mut messages: Vec<String> = vec![];
+    let mut prev_empty = true;
+
+    for line in text.split_inclusive("\n") {
+        if (prev_empty && line.starts_with("From ")) || messages.is_empty() {
+            // The empty line separates the messages and isn't part of them
+            if let Some(last) = messages.last_mut() {
+                if last.ends_with("\n\n") {
+                    last.pop();
+                }
+            }
+            messages.push(String::new());
+        }
+        prev_empty = line.trim_end().is_empty();
+        messages.last_mut().unwrap().push_str(line);
+    }
+
+    messages.retain(|m| m.contains("diff --git "));
+    messages
+}
+
+// Load a series from a directory of patch files, an mbox file or an a..b range
+pub fn load(options: &Options, what: &str) -> Result<Vec<SeriesPatch>, Box<dyn Error>> {
+    let path = Path::new(what);
+
+    if path.is_dir() {
+        let mut files: Vec<String> = vec![];
+        for entry in fs::read_dir(path)? {
+            let entry = entry?.path();
+            if entry.is_file() && entry.extension().is_some_and(|e| e == "patch" || e == "diff") {
+                files.push(entry.display().to_string());
+            }
+        }
+        files.sort();
+
+        let mut series = vec![];
+        for file in files {
+            let name = Path::new(&file).file_name().unwrap().to_string_lossy().to_string();
+            series.push(SeriesPatch::parse(&name, &fs::read_to_string(&file)?));
+        }
+        return Ok(series);
+    }
+
+    let text = if path.is_file() {
+        fs::read_to_string(path)?
+    } else if what.contains("..") {
+        let git_dir = options.git_dir.clone().unwrap();
+        Git::cmd(format!("format-patch --stdout {}", Git::quote(what)), &git_dir)
+            .map_err(|_| format!("Not a patch directory, mbox or range: {}", what).red())?
+    } else {
+        return Err(format!("Not a patch directory, mbox or range: {}", what).red().into());
+    };
+
+    Ok(split_mbox(&text).iter().enumerate().map(|(i, m)| SeriesPatch::parse(&format!("#{}", i + 1), m)).collect())
+}
+
+// Pair up the patches of two series. Returns the pairs, then the patches only in the first
+// and only in the second series
+pub fn pair(a: &[SeriesPatch], b: &[SeriesPatch]) -> (Vec<(usize, usize)>, Vec<usize>, Vec<usize>) {
+    let mut pairs: Vec<(usize, usize)> = vec![];
+    let mut taken = vec![false; b.len()];
+    let mut unpaired: Vec<usize> = vec![];
+
+    // Hashes are the stronger match so pair all of them before looking at subjects
+    for (i, patch) in a.iter().enumerate() {
+        match (0..b.len()).find(|j| !taken[*j] && patch.same_commit(&b[*j])) {
+            Some(j) => {
+                taken[j] = true;
+                pairs.push((i, j));
+            },
+            None => unpaired.push(i),
+        }
+    }
+
+    let mut removed: Vec<usize> = vec![];
+    for i in unpaired {
+        match (0..b.len()).find(|j| !taken[*j] && !a[i].subject.is_empty() && a[i].subject == b[*j].subject) {
+            Some(j) => {
+                taken[j] = true;
+                pairs.push((i, j));
+            },
+            None => removed.push(i),
+        }
+    }
+
+    pairs.sort();
+    let added = (0..b.len()).filter(|j| !taken[*j]).collect();
+
+    (pairs, removed, added)
+}
@@ -0,0 +1,184 @@
+use std::error::Error;
+use clap::{ArgMatches, Command};
+use colored::Colorize;
+use crate::Options;
+use crate::Log;
+use crate::git::Git;
+use crate::flavor::Flavor;
+use crate::export;
+use crate::message::MessageStyle;
+use crate::message::Message;
+
+/* Linux stable backports. Each backport starts with a reference to upstream
+ *
+ * commit <upstream hash> upstream.
+ *
+ * Older stable releases used "[ Upstream commit <hash> ]" which is accepted as well.
+ * Local stable branches matching stable-branches (linux-*.y by default) are scanned for the
+ * references to find the stable releases carrying a commit, and stable-target (the branch point
+ * by default) is the release backported to
+ */
+pub struct Stable {
+}
+
+// Returns the upstream commits referenced in a commit message
+fn upstream_refs(text: &str) -> Vec<String> {
+    let mut refs = vec![];
+
+    for line in text.split("\n") {
+        let words: Vec<&str> = line.trim().trim_start_matches("[").trim_end_matches("]").split_whitespace().collect();
+
+        if words.len() == 3 && words[0] == "commit" && words[2] == "upstream." {
+            refs.push(words[1].to_string());
+        } else if words.len() == 3 && words[0] == "Upstream" && words[1] == "commit" {
+            refs.push(words[2].to_string());
+        }
+    }
+
+    refs.retain(|r| r.len() == 40 && r.chars().all(|c| c.is_ascii_hexdigit()));
+    refs
+}
+
+// Returns (stable hash, upstream hash) of the backports on a stable branch since range-start
+fn stable_picks(options: &Options, reference: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let git_dir = options.git_dir.clone().unwrap();
+    let range_start = options.range_start.clone().unwrap();
+    let grep = Git::quote("^commit [0-9a-f]{40} upstream\\.|Upstream commit [0-9a-f]{40}");
+
+    let stdout = Git::cmd(format!("log --no-merges -E --grep={} --format='%H%n%B%x00' {}..{}",
+                                  grep, range_start, reference), &git_dir)?;
+    let mut picks = vec![];
+
+    for entry in stdout.split("\0") {
+        let entry = entry.trim();
+        let (hash, body) = match entry.split_once("\n") {
+            Some(parts) => parts,
+            None => continue,
+        };
+
+        for upstream in upstream_refs(body) {
+            picks.push((hash.to_string(), upstream));
+        }
+    }
+
+    Ok(picks)
+}
+
+impl Flavor for Stable {
+    fn name(&self) -> &str {
+        "stable"
+    }
+
+    fn config_keys(&self) -> Vec<(&'static str, &'static str)> {
+        vec![
+            ("stable-branches", "pattern of the local stable branches, linux-*.y by default"),
+            ("stable-target", "stable release backported to, the branch point by default"),
+        ]
+    }
+
+    fn command(&self) -> Option<Command> {
+        Some(Command::new("stable")
+            .about("Linux stable specific subcommands")
+            .arg_required_else_help(true)
+            .subcommand(
+                Command::new("scan")
+                    .about("record which local stable branches carry the upstream commits")
+            ))
+    }
+
+    fn run(&self, options: &mut Options, log: &mut Log, matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+        match matches.subcommand() {
+            Some(("scan", _sub_m)) => cmd_stable_scan(options, log),
+            _ => Ok(()),
+        }
+    }
+
+    fn tag_message(&self, _options: &Options, _log: &Log, upstream: &str, text: &str) -> Result<String, Box<dyn Error>> {
+        let mut msg = Message::parse(text);
+
+        if !upstream_refs(&msg.body).contains(&upstream.to_string()) {
+            msg.add_header(&format!("commit {} upstream.", upstream));
+        }
+
+        Ok(msg.text())
+    }
+
+    fn validate(&self, _options: &Options, _log: &Log, upstream: Option<&str>, text: &str) -> Vec<String> {
+        let upstream = match upstream {
+            Some(upstream) => upstream,
+            None => return vec!["Not a backport of an upstream commit".to_string()],
+        };
+
+        let msg = Message::parse(text);
+        match msg.body.split("\n").next() {
+            Some(first) if upstream_refs(first).contains(&upstream.to_string()) => vec![],
+            _ => vec![format!("Body doesn't start with commit {} upstream.", upstream)],
+        }
+    }
+
+    fn export(&self, options: &Options, log: &Log) -> Result<(), Box<dyn Error>> {
+        export::export_series(options, log, MessageStyle::Stable)
+    }
+
+    // Backports already in the target release are marked like cherry picks
+    fn picks(&self, options: &Options) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        let target = options.flavor_value("stable-target").unwrap_or_else(|| options.branch_point.clone().unwrap());
+
+        stable_picks(options, &target)
+    }
+}
+
+// Returns the first stable release containing hash, or the branch if it's not released yet
+fn stable_version(options: &Options, branch: &str, hash: &str) -> String {
+    let git_dir = options.git_dir.clone().unwrap();
+
+    match Git::cmd(format!("describe --contains --match 'v*' {}", hash), &git_dir) {
+        Ok(line) => line.split("~").next().unwrap_or_default().trim().to_string(),
+        Err(_) => branch.to_string(),
+    }
+}
+
+// Record in the log which stable releases already carry each upstream commit
+fn cmd_stable_scan(options: &Options, log: &mut Log) -> Result<(), Box<dyn Error>> {
+    let git_dir = options.git_dir.clone().unwrap();
+    let pattern = options.flavor_value("stable-branches").unwrap_or("linux-*.y".to_string());
+
+    let branches = Git::cmd(format!("for-each-ref --format='%(refname:short)' {}",
+                                    Git::quote(&format!("refs/heads/{}", pattern))), &git_dir)?;
+    let branches: Vec<&str> = branches.split_whitespace().collect();
+    if branches.is_empty() {
+        return Err(format!("No local stable branches matching {}", pattern).red().into());
+    }
+
+    let mut picks: Vec<(&str, String, String)> = vec![];
+    for branch in &branches {
+        println!("Scanning {}", branch);
+        for (stable, upstream) in stable_picks(options, branch)? {
+            picks.push((branch, stable, upstream));
+        }
+    }
+
+    let mut found = 0;
+    let mut annotations: Vec<(String, String)> = vec![];
+    for (upstream, _) in log.get_all()? {
+        let mut versions: Vec<String> = vec![];
+        for (branch, stable, _) in picks.iter().filter(|p| p.2 == upstream) {
+            let version = stable_version(options, branch, stable);
+            if !versions.contains(&version) {
+                versions.push(version);
+            }
+        }
+
+        if !versions.is_empty() {
+            found += 1;
+        } else if log.get_annotation(&upstream, "stable").is_none() {
+            continue;
+        }
+        annotations.push((upstream, versions.join(" ")));
+    }
+    log.set_annotations("stable", &annotations)?;
+
+    println!("{} of {} commits are in stable releases", found, log.get_all()?.len());
+
+    Ok(())
+}
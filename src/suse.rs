@@ -8,50 +8,121 @@ use crate::git::{Git, GitSessionState};
 use crate::Util;
 use crate::commands::*;
 use crate::patch::CompareResult;
+use crate::flavor;
+use crate::flavor::Flavor;
 use clap::{ArgMatches, Command};
 use colored::Colorize;
 
-pub fn cmd_suse(options: &mut Options, log: &Log, subcommand: &mut Command, matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
-    let signature = matches.get_one::<String>("signature").cloned();
-    let references = matches.get_one::<String>("patch references").cloned();
-    let kernel_source = matches.get_one::<String>("Path to SUSE kernel-source").cloned();
+// SUSE tags live in the patch headers of kernel-source and are added by suse export, so the
+// backported commits only need to reference an upstream commit
+pub struct Suse {
+}
+
+// Config keys every suse subcommand needs
+const REQUIRED_KEYS: [&str; 3] = ["signature", "references", "suse-kernel-source"];
 
-    if signature.is_some() {
-        options.signature = signature;
-    } else if options.signature.is_none() {
-        return Err("suse subcommands requires option --signature to be specified".red().into());
+fn suse_config(options: &Options, key: &str) -> Result<String, Box<dyn Error>> {
+    match options.flavor_value(key) {
+        Some(value) => Ok(value),
+        None => Err(format!("suse subcommands require option --{} to be specified", key).red().into()),
     }
+}
 
-    if references.is_some() {
-        options.references = references;
-    } else if options.references.is_none() {
-        return Err("suse subcommands require option --references to be specified".red().into());
+fn range_guard(options: &Options) -> String {
+    options.flavor_value("range-guard").unwrap_or(options.range_stop.clone().unwrap())
+}
+
+fn guard_prefix(options: &Options) -> String {
+    options.flavor_value("guard-prefix").unwrap_or("b2tf".to_string())
+}
+
+fn check_config(options: &Options) -> Result<(), Box<dyn Error>> {
+    for key in REQUIRED_KEYS {
+        suse_config(options, key)?;
     }
 
-    if kernel_source.is_some() {
-        options.kernel_source = kernel_source;
-    } else if options.kernel_source.is_none() {
-        return Err("suse subcommands require option --suse-kernel-source to be specified".red().into());
+    Ok(())
+}
+
+impl Flavor for Suse {
+    fn name(&self) -> &str {
+        "suse"
     }
 
-    match matches.subcommand() {
-        Some(("export", _sub_m)) => {
-            cmd_suse_export(options, log)?;
-        },
-        Some(("unblacklist", _sub_m)) => {
-            cmd_suse_unblacklist(options)?;
-        },
-        Some(("apply", _sub_m)) => {
-            cmd_suse_apply(options)?;
-        },
-        Some(("unguard", _sub_m)) => {
-            cmd_suse_unguard(options)?;
-        },
-        Some((&_, _)) => {},
-        None => {let _ = subcommand.print_help();},
+    fn config_keys(&self) -> Vec<(&'static str, &'static str)> {
+        vec![
+            ("signature", "Acked-by signature added to exported patches"),
+            ("references", "References added to exported patches"),
+            ("suse-kernel-source", "path to SUSE kernel-source"),
+            ("range-guard", "automatically guard failing patches up to this revision"),
+            ("guard-prefix", "prefix to use when guarding and unguarding patches"),
+        ]
     }
 
-    Ok(())
+    fn command(&self) -> Option<Command> {
+        Some(Command::new("suse")
+            .about("SUSE specific subcommands")
+            .arg_required_else_help(true)
+            .subcommand(
+                Command::new("export")
+                    .about("export all commits as SUSE patch files")
+            )
+            .subcommand(
+                Command::new("unblacklist")
+                    .about("remove blacklists for patches we are backporting")
+            )
+            .subcommand(
+                Command::new("apply")
+                    .about("apply all patches to the SUSE tree")
+            )
+            .subcommand(
+                Command::new("unguard")
+                    .about("reapply all guarded patches in series.conf")
+            ))
+    }
+
+    fn run(&self, options: &mut Options, log: &mut Log, matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+        check_config(options)?;
+
+        match matches.subcommand() {
+            Some(("export", _sub_m)) => {
+                cmd_suse_export(options, log)?;
+            },
+            Some(("unblacklist", _sub_m)) => {
+                cmd_suse_unblacklist(options)?;
+            },
+            Some(("apply", _sub_m)) => {
+                cmd_suse_apply(options)?;
+            },
+            Some(("unguard", _sub_m)) => {
+                cmd_suse_unguard(options, log)?;
+            },
+            _ => {},
+        }
+
+        Ok(())
+    }
+
+    fn tag_message(&self, _options: &Options, _log: &Log, _upstream: &str, text: &str) -> Result<String, Box<dyn Error>> {
+        Ok(text.to_string())
+    }
+
+    fn validate(&self, _options: &Options, _log: &Log, upstream: Option<&str>, _text: &str) -> Vec<String> {
+        match upstream {
+            Some(_) => vec![],
+            None => vec!["Not in the log, suse export can't find its Git-commit".to_string()],
+        }
+    }
+
+    fn export(&self, options: &Options, log: &Log) -> Result<(), Box<dyn Error>> {
+        check_config(options)?;
+        cmd_suse_export(options, log)
+    }
+
+    fn apply_downstream(&self, options: &Options, _log: &Log) -> Result<(), Box<dyn Error>> {
+        check_config(options)?;
+        cmd_suse_apply(options)
+    }
 }
 
 pub fn cmd_suse_export(options: &Options, log: &Log) -> Result<(), Box<dyn Error>> {
@@ -60,9 +131,9 @@ pub fn cmd_suse_export(options: &Options, log: &Log) -> Result<(), Box<dyn Error
     let branch = options.branch.clone().unwrap();
     let branch_point = options.branch_point.clone().unwrap();
     let work_dir = path::absolute(&work_dir)?.into_os_string().into_string().unwrap();
-    let kernel_source = options.kernel_source.clone().unwrap();
-    let signature = options.signature.clone().unwrap();
-    let references = options.references.clone().unwrap();
+    let kernel_source = suse_config(options, "suse-kernel-source")?;
+    let signature = suse_config(options, "signature")?;
+    let references = suse_config(options, "references")?;
 
     println!("Exporting patches into {}patches.suse/", work_dir);
     Git::cmd(format!("format-patch -o {}/patches.suse/ --no-renames --keep-subject {}..{}",
@@ -99,11 +170,13 @@ pub fn cmd_suse_export(options: &Options, log: &Log) -> Result<(), Box<dyn Error
         contents = lines.join("\n");
         fs::write(&file_path, contents)?;
 
-        // Add Git-commit tag
-        add_suse_tag(&file_path, &kernel_source, "Git-commit", &hash_up)?;
+        // Add Git-commit tag. Submitted patches are only on the scratch branch of the import
+        if log.get_annotation(&hash_up, "submitted").is_none() {
+            add_suse_tag(&file_path, &kernel_source, "Git-commit", &hash_up)?;
+        }
 
         // Add mainline tag
-        let mainline = get_mainline_tag(&hash_up, &git_dir)?;
+        let mainline = flavor::mainline_tag(&hash_up, log, &git_dir)?;
         add_suse_tag(&file_path, &kernel_source, "Patch-mainline", &mainline)?;
 
         // Add Acked-by tag
@@ -185,7 +258,7 @@ fn compare_commits(list1: &Vec<String>, list2: &Vec<String>) -> bool {
 
 pub fn cmd_suse_unblacklist(options: &Options) -> Result<(), Box<dyn Error>> {
     let work_dir = options.work_dir.clone().unwrap();
-    let kernel_source = options.kernel_source.clone().unwrap();
+    let kernel_source = suse_config(options, "suse-kernel-source")?;
 
     println!("Removing blacklist entries in blacklist.conf...");
 
@@ -269,14 +342,6 @@ fn add_suse_tag(file_path: &String, kernel_source: &String, tag: &str, value: &s
     Ok(())
 }
 
-fn get_mainline_tag(hash: &str, git_dir: &String) -> Result<String, Box<dyn Error>> {
-    let line = Git::cmd(format!("describe --contains --match 'v*' {}", hash), git_dir)?;
-    let tag: Vec<&str> = line.split("~").collect();
-    let mainline = tag[0].to_string();
-
-    Ok(mainline)
-}
-
 fn get_ref_link(r: &str) -> String {
     let t: Vec<&str> = r.split("#").collect();
 
@@ -426,7 +491,7 @@ fn series_sort(kernel_source: &String) -> Result<(), Box<dyn Error>> {
 
 fn sequence_patch(options: &Options, range_guard_commits: &Vec<String>, file_name: &String,
                   processed_commits: &mut Vec<Vec<String>>) -> Result<(), Box<dyn Error>> {
-    let kernel_source = options.kernel_source.clone().unwrap();
+    let kernel_source = suse_config(options, "suse-kernel-source")?;
 
     'outer: loop {
         let output = Cmd::new("sh")
@@ -539,7 +604,7 @@ fn series_unstage(kernel_source: &String, file_name: &String) -> Result<(), Box<
 }
 
 fn suse_log_entry(options: &Options, entry: (String, String), actions: &mut Vec<String>) -> Result<(), Box<dyn Error>> {
-    let kernel_source = options.kernel_source.clone().unwrap();
+    let kernel_source = suse_config(options, "suse-kernel-source")?;
 
     // Don't create an entry for series.conf
     if entry.1 == "series.conf" {
@@ -586,7 +651,7 @@ fn suse_log_entry(options: &Options, entry: (String, String), actions: &mut Vec<
 }
 
 fn suse_log(options: &Options, msg: &str) -> Result<(), Box<dyn Error>> {
-    let kernel_source = options.kernel_source.clone().unwrap();
+    let kernel_source = suse_config(options, "suse-kernel-source")?;
     let session = Git::get_session(&kernel_source)?;
 
     // If only series.conf is modified we are unguarding and scripts/log doesn't work
@@ -652,8 +717,8 @@ fn check_guard(file_name: &str, kernel_source: &String) -> Result<Option<String>
 
 // Mark a patch with +{guard_prefix} in series.conf. Returns true if previously guarded
 fn insert_guard(options: &Options, file_name: &str, processed_commits: &Vec<Vec<String>>) -> Result<bool, Box<dyn Error>> {
-    let kernel_source = options.kernel_source.clone().unwrap();
-    let guard_prefix = options.guard_prefix.clone().unwrap();
+    let kernel_source = suse_config(options, "suse-kernel-source")?;
+    let guard_prefix = guard_prefix(options);
     let series_path = format!("{}/series.conf", kernel_source);
     let path = format!("patches.suse/{}", file_name.trim());
     let file = fs::read_to_string(&series_path)?;
@@ -704,8 +769,8 @@ fn insert_guard(options: &Options, file_name: &str, processed_commits: &Vec<Vec<
 
 // Remove a patch marked with +{guard_prefix} in series.conf
 fn remove_guard(options: &Options, file_name: &str) -> Result<(), Box<dyn Error>> {
-    let kernel_source = options.kernel_source.clone().unwrap();
-    let guard_prefix = options.guard_prefix.clone().unwrap();
+    let kernel_source = suse_config(options, "suse-kernel-source")?;
+    let guard_prefix = guard_prefix(options);
     let series_path = format!("{}/series.conf", kernel_source);
     let path = format!("patches.suse/{}", file_name);
     let file = fs::read_to_string(&series_path)?;
@@ -762,12 +827,22 @@ fn replace_patch(file_path: &String, suse_path: &String, kernel_source: &String,
         }
     }
 
-    match compare_patches(suse_path, file_path)? {
+    let comp_res = compare_patches(suse_path, file_path)?;
+    match comp_res {
         CompareResult::Different => println!("Patches are different"),
+        CompareResult::Equivalent => println!("Patches have the same changes when ignoring whitespace, context and renames"),
         CompareResult::Similar => println!("Patches have the same changes but at different lines"),
         CompareResult::Same => println!("Patches have identical changes but have other differences"),
         CompareResult::Identical => println!("Patches are identical"),
     }
+    if comp_res < CompareResult::Identical {
+        for reason in patch_differences(suse_path, file_path)? {
+            println!("  {}", reason);
+        }
+    }
+
+    // Equivalent patches rarely need to be replaced so default to keeping them
+    let default = if comp_res == CompareResult::Equivalent { "n" } else { "y" };
 
     let mut handled = false;
     for r in &refs {
@@ -779,8 +854,9 @@ fn replace_patch(file_path: &String, suse_path: &String, kernel_source: &String,
         print!("{}", get_ref_link(&r).yellow());
 
         loop {
-            let ask = Util::ask("(Y)es, (n)o, (a)lways, n(e)ver, (v)iew, or (s)top: ".to_string(),
-                                vec!["y", "n", "a", "e", "v", "s"], "y");
+            let prompt = if default == "y" { "(Y)es, (n)o" } else { "(y)es, (N)o" };
+            let ask = Util::ask(format!("{}, (a)lways, n(e)ver, (v)iew, or (s)top: ", prompt),
+                                vec!["y", "n", "a", "e", "v", "s"], default);
 
             // FIXME: Support other editors
             match ask.as_str() {
@@ -824,14 +900,14 @@ fn replace_patch(file_path: &String, suse_path: &String, kernel_source: &String,
 
 pub fn cmd_suse_apply(options: &Options) -> Result<(), Box<dyn Error>> {
     let work_dir = options.work_dir.clone().unwrap();
-    let kernel_source = options.kernel_source.clone().unwrap();
+    let kernel_source = suse_config(options, "suse-kernel-source")?;
     let git_dir = options.git_dir.clone().unwrap();
     let paths = options.paths.clone().unwrap();
     let range_start = options.range_start.clone().unwrap();
-    let guard_prefix = options.guard_prefix.clone().unwrap();
+    let guard_prefix = guard_prefix(options);
 
     // Create a list of all commits in the range_start..range_guard range
-    let range_guard = options.range_guard.clone().unwrap();
+    let range_guard = range_guard(options);
     let query = format!("log --format='%H' {range_start}..{range_guard} -- {paths}").to_string();
     let commits = Git::cmd(query, &git_dir)?;
     let commits: Vec<&str> = commits.split("\n").collect();
@@ -958,8 +1034,8 @@ pub fn cmd_suse_apply(options: &Options) -> Result<(), Box<dyn Error>> {
 
 // Returns a list of all guarded patches
 fn get_guards(options: &Options) -> Result<Vec<String>, Box<dyn Error>> {
-    let kernel_source = options.kernel_source.clone().unwrap();
-    let guard_prefix = format!("+{}", options.guard_prefix.clone().unwrap());
+    let kernel_source = suse_config(options, "suse-kernel-source")?;
+    let guard_prefix = format!("+{}", guard_prefix(options));
     let series_path = format!("{}/series.conf", kernel_source);
     let file = fs::read_to_string(&series_path)?;
     let lines: Vec<&str> = file.split("\n").collect();
@@ -1003,7 +1079,7 @@ fn sequence_test(kernel_source: &String) -> Result<String, Box<dyn Error>> {
 
 // Loop over failing patches after we've unguarded a patch
 fn sequence_unguard(options: &Options, path: &String) -> Result<(), Box<dyn Error>> {
-    let kernel_source = options.kernel_source.clone().unwrap();
+    let kernel_source = suse_config(options, "suse-kernel-source")?;
 
     loop {
         let output = sequence_test(&kernel_source)?;
@@ -1042,12 +1118,12 @@ fn sequence_unguard(options: &Options, path: &String) -> Result<(), Box<dyn Erro
     Ok(())
 }
 
-fn handle_unguard(options: &Options, path: &String) -> Result<(), Box<dyn Error>> {
+fn handle_unguard(options: &Options, log: &Log, path: &String) -> Result<(), Box<dyn Error>> {
     let work_dir = options.work_dir.clone().unwrap();
-    let kernel_source = options.kernel_source.clone().unwrap();
+    let kernel_source = suse_config(options, "suse-kernel-source")?;
     let git_dir = options.git_dir.clone().unwrap();
-    let signature = options.signature.clone().unwrap();
-    let references = options.references.clone().unwrap();
+    let signature = suse_config(options, "signature")?;
+    let references = suse_config(options, "references")?;
 
     let commit = get_suse_tags(&path, &kernel_source, "Git-commit")?;
     if commit.len() != 1 {
@@ -1065,7 +1141,7 @@ fn handle_unguard(options: &Options, path: &String) -> Result<(), Box<dyn Error>
     add_suse_tag(&upstream_path, &kernel_source, "Git-commit", &commit)?;
 
     // Add mainline tag
-    let mainline = get_mainline_tag(&commit, &git_dir)?;
+    let mainline = flavor::mainline_tag(&commit, log, &git_dir)?;
     add_suse_tag(&upstream_path, &kernel_source, "Patch-mainline", &mainline)?;
 
     // Add Acked-by tag
@@ -1156,8 +1232,8 @@ fn handle_unguard(options: &Options, path: &String) -> Result<(), Box<dyn Error>
     Ok(())
 }
 
-pub fn cmd_suse_unguard(options: &Options) -> Result<(), Box<dyn Error>> {
-    let kernel_source = options.kernel_source.clone().unwrap();
+pub fn cmd_suse_unguard(options: &Options, log: &Log) -> Result<(), Box<dyn Error>> {
+    let kernel_source = suse_config(options, "suse-kernel-source")?;
     let session = Git::get_session(&kernel_source)?;
 
     if session.state != GitSessionState::None {
@@ -1170,7 +1246,7 @@ pub fn cmd_suse_unguard(options: &Options) -> Result<(), Box<dyn Error>> {
 
     for path in paths {
         println!("Progress: {}/{}", i, num);
-        handle_unguard(options, &path)?;
+        handle_unguard(options, log, &path)?;
         i += 1;
     }
 
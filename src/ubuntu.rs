@@ -0,0 +1,107 @@
+use std::error::Error;
+use crate::Options;
+use crate::Log;
+use crate::flavor::Flavor;
+use crate::commands::signoff_line;
+use crate::message::Message;
+
+/* Ubuntu and Debian derived kernels. The bugs come from the buglink config key
+ *
+ * BugLink: https://bugs.launchpad.net/bugs/1234
+ *
+ * <upstream message>
+ * (cherry picked from commit X)    - or (backported from commit X) if it was resolved by hand
+ * Signed-off-by: <backporter>
+ *
+ * Commits that are not backports are SAUCE and their subject starts with "UBUNTU:"
+ */
+pub struct Ubuntu {
+}
+
+fn buglinks(options: &Options) -> Vec<String> {
+    options.flavor_value("buglink").unwrap_or_default().split_whitespace().map(|bug| {
+        if bug.starts_with("http") {
+            format!("BugLink: {}", bug)
+        } else {
+            format!("BugLink: https://bugs.launchpad.net/bugs/{}", bug)
+        }
+    }).collect()
+}
+
+impl Flavor for Ubuntu {
+    fn name(&self) -> &str {
+        "ubuntu"
+    }
+
+    fn config_keys(&self) -> Vec<(&'static str, &'static str)> {
+        vec![("buglink", "Launchpad bugs the backport is for")]
+    }
+
+    fn tag_message(&self, options: &Options, log: &Log, upstream: &str, text: &str) -> Result<String, Box<dyn Error>> {
+        let mut msg = Message::parse(text);
+
+        // The reference depends on whether the pick had to be resolved by hand, so replace
+        // one that was added by the message style
+        let picked = format!("(cherry picked from commit {})", upstream);
+        let backported = format!("(backported from commit {})", upstream);
+        msg.body = msg.body.split("\n").filter(|l| l.trim() != picked && l.trim() != backported)
+            .collect::<Vec<&str>>().join("\n").trim_end().to_string();
+
+        if log.get_annotation(upstream, "conflicts").is_some() {
+            msg.add_trailer(&backported);
+        } else {
+            msg.add_trailer(&picked);
+        }
+
+        // The backporter signs off below the reference
+        let signoff = signoff_line(options)?;
+        let lines: Vec<&str> = msg.body.split("\n").collect();
+        if lines.last().map(|l| l.trim()) != Some(signoff.as_str()) {
+            msg.add_trailer(&signoff);
+        }
+
+        // Added last so the links stay a paragraph of their own if the body was empty. Commits
+        // left without a BugLink: are reported by validate
+        let links: Vec<String> = buglinks(options).into_iter().filter(|l| !msg.contains(l)).collect();
+        if !links.is_empty() {
+            msg.add_header(&links.join("\n"));
+        }
+
+        Ok(msg.text())
+    }
+
+    fn validate(&self, _options: &Options, _log: &Log, upstream: Option<&str>, text: &str) -> Vec<String> {
+        let msg = Message::parse(text);
+        let lines: Vec<&str> = msg.body.split("\n").map(|l| l.trim()).collect();
+        let mut problems = vec![];
+
+        if !lines.iter().any(|l| l.starts_with("BugLink:")) {
+            problems.push("No BugLink: line".to_string());
+        }
+
+        let upstream = match upstream {
+            Some(upstream) => upstream,
+            None => {
+                if !msg.subject.starts_with("UBUNTU:") {
+                    problems.push("Not a backport and the subject doesn't start with UBUNTU:".to_string());
+                }
+                return problems;
+            },
+        };
+
+        let picked = format!("(cherry picked from commit {})", upstream);
+        let backported = format!("(backported from commit {})", upstream);
+        let reference = lines.iter().position(|l| *l == picked || *l == backported);
+
+        match reference {
+            None => problems.push(format!("No (cherry picked from commit {}) or (backported from ...) line", upstream)),
+            Some(pos) => {
+                if !lines[pos + 1..].iter().any(|l| l.starts_with("Signed-off-by:")) {
+                    problems.push("No Signed-off-by: of the backporter below the reference".to_string());
+                }
+            },
+        }
+
+        problems
+    }
+}
@@ -0,0 +1,134 @@
+use std::process::Command;
+use std::error::Error;
+use colored::Colorize;
+use crate::Options;
+use crate::Log;
+use crate::git::Git;
+
+// Run the verify command in the git directory. Returns true if it succeeded
+pub fn run(options: &Options) -> Result<bool, Box<dyn Error>> {
+    let git_dir = options.git_dir.clone().unwrap();
+    let verify = options.verify.clone().unwrap();
+
+    println!("{} {}", "Verifying:".bright_blue(), verify);
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("cd {} && {}", git_dir, verify))
+        .status()
+        .expect("Failed to run verify command");
+
+    Ok(status.success())
+}
+
+// Returns the number of applied commits to verify in one go
+pub fn interval(options: &Options) -> usize {
+    match &options.verify_interval {
+        Some(interval) => interval.parse::<usize>().unwrap_or(1).max(1),
+        None => 1,
+    }
+}
+
+// Find the first backport failing verification by checking out the commits one by one
+fn bisect(options: &Options, backports: &[String]) -> Result<usize, Box<dyn Error>> {
+    let git_dir = options.git_dir.clone().unwrap();
+    let branch = options.branch.clone().unwrap();
+
+    // The last commit is known to fail and everything before the first is known to work
+    let mut lo = 0;
+    let mut hi = backports.len() - 1;
+
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        println!("{} {}", "Bisecting at".bright_blue(), backports[mid]);
+
+        Git::cmd(format!("checkout -q --detach {}", backports[mid]), &git_dir)?;
+        let res = run(options);
+        Git::cmd(format!("checkout -q {}", branch), &git_dir)?;
+
+        if res? {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+
+    Ok(lo)
+}
+
+// Verify the commits applied since the last verification. If force is false we only verify when
+// the interval is reached. On failure the offending commit is recorded in the log
+pub fn check(options: &Options, log: &mut Log, unverified: &mut Vec<String>, force: bool) -> Result<(), Box<dyn Error>> {
+    if options.verify.is_none() || unverified.is_empty() {
+        return Ok(());
+    }
+
+    if !force && unverified.len() < interval(options) {
+        return Ok(());
+    }
+
+    if run(options)? {
+        unverified.clear();
+        return Ok(());
+    }
+
+    // Map the upstream commits to the backports that are actually on the branch
+    let commits = log.get_all()?;
+    let mut upstreams: Vec<String> = vec![];
+    let mut backports: Vec<String> = vec![];
+
+    for hash in unverified.iter() {
+        let backport = commits.iter().find(|c| c.0 == *hash).map(|c| c.1.clone()).unwrap_or_default();
+        if backport.len() == 40 && !backports.contains(&backport) {
+            upstreams.push(hash.clone());
+            backports.push(backport);
+        }
+    }
+
+    if backports.is_empty() {
+        return Err("Verification failed".red().into());
+    }
+
+    let mut culprit = backports.len() - 1;
+    let mut note = format!("failed: {}", options.verify.clone().unwrap());
+
+    if backports.len() > 1 {
+        if options.verify_bisect {
+            culprit = bisect(options, &backports)?;
+        } else {
+            note = format!("failed in batch of {} commits: {}", backports.len(), options.verify.clone().unwrap());
+        }
+    }
+
+    let upstream = &upstreams[culprit];
+    log.set_annotation(upstream, "verify", &note)?;
+
+    let commit = Git::show(upstream, &options.git_dir.clone().unwrap())?;
+    Err(format!("Verification failed at {} ({}) {}", upstream, backports[culprit], commit.subject).red().into())
+}
+
+// Verify commits that previously failed verification and clear the failures if they're fixed
+pub fn recheck(options: &Options, log: &mut Log) -> Result<(), Box<dyn Error>> {
+    if options.verify.is_none() {
+        return Ok(());
+    }
+
+    let failed: Vec<String> = log.get_all()?.into_iter()
+        .filter(|c| log.get_annotation(&c.0, "verify").is_some())
+        .map(|c| c.0)
+        .collect();
+
+    if failed.is_empty() {
+        return Ok(());
+    }
+
+    if !run(options)? {
+        return Err("Verification still fails. Fix the branch before continuing".red().into());
+    }
+
+    for hash in failed.iter() {
+        log.set_annotation(hash, "verify", "")?;
+    }
+
+    Ok(())
+}